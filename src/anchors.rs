@@ -0,0 +1,208 @@
+//! Anchors and aliases for repeated subtrees in written YAML. serde_yaml
+//! expands aliases when reading and never writes them, so sharing is
+//! restored on the serialized text instead.
+
+use crate::docs::split_key;
+use std::collections::{HashMap, HashSet};
+
+/// Subtrees spanning fewer lines are written out every time.
+const MIN_LINES: usize = 2;
+
+/// A key whose value is a block mapping or sequence.
+struct Block {
+    key: String,
+    /// Index of the key line and one past the value's last line.
+    start: usize,
+    end: usize,
+    /// The value's lines with the key's indentation removed.
+    text: String,
+}
+
+/// Rewrites `yaml` so that the value of every key that repeats an earlier
+/// key's value is an alias of it.
+pub(crate) fn share_repeated(yaml: &str) -> String {
+    let lines: Vec<&str> = yaml.lines().collect();
+    let blocks = blocks(&lines);
+
+    // Values inside a value that becomes an alias disappear with it.
+    let mut first: HashMap<&str, usize> = HashMap::new();
+    let mut aliases = HashMap::new();
+    let mut skip_until = 0;
+    for (i, b) in blocks.iter().enumerate() {
+        if b.start < skip_until {
+            continue;
+        }
+        match first.get(b.text.as_str()) {
+            Some(&f) => {
+                aliases.insert(b.start, (f, b.end));
+                skip_until = b.end;
+            }
+            None => {
+                first.insert(&b.text, i);
+            }
+        }
+    }
+    if aliases.is_empty() {
+        return yaml.to_owned();
+    }
+
+    let mut anchored: Vec<usize> = aliases.values().map(|&(f, _)| f).collect();
+    anchored.sort_unstable();
+    anchored.dedup();
+    let mut used = HashSet::new();
+    let names: HashMap<usize, String> = anchored
+        .into_iter()
+        .map(|i| (blocks[i].start, anchor_name(&blocks[i].key, &mut used)))
+        .collect();
+
+    let mut out = String::with_capacity(yaml.len());
+    let mut i = 0;
+    while i < lines.len() {
+        out.push_str(lines[i]);
+        if let Some(&(f, end)) = aliases.get(&i) {
+            out.push_str(" *");
+            out.push_str(&names[&blocks[f].start]);
+            out.push('\n');
+            i = end;
+            continue;
+        }
+        if let Some(name) = names.get(&i) {
+            out.push_str(" &");
+            out.push_str(name);
+        }
+        out.push('\n');
+        i += 1;
+    }
+    out
+}
+
+fn blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    // Indentation of the line whose block scalar (`|` or `>`) is being
+    // read. Its lines are string content, even if they look like keys.
+    let mut scalar = None;
+    for (start, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some(scalar_indent) = scalar {
+            if trimmed.is_empty() || indent > scalar_indent {
+                continue;
+            }
+            scalar = None;
+        }
+        if opens_block_scalar(trimmed) {
+            scalar = Some(indent);
+        }
+        if trimmed.starts_with('-') || trimmed.starts_with('#') {
+            continue;
+        }
+        let key = match split_key(trimmed) {
+            Some((key, rest)) if rest.trim().is_empty() => key,
+            _ => continue,
+        };
+        // Block sequences under a key are written at the key's indentation.
+        let is_item = |l: &str| {
+            let t = l.trim_start();
+            l.len() - t.len() == indent && (t == "-" || t.starts_with("- "))
+        };
+        let seq = lines.get(start + 1).is_some_and(|l| is_item(l));
+        let mut end = start + 1;
+        while let Some(l) = lines.get(end) {
+            let t = l.trim_start();
+            if t.is_empty() || l.len() - t.len() > indent || (seq && is_item(l)) {
+                end += 1;
+            } else {
+                break;
+            }
+        }
+        while end > start + 1 && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+        if end - start - 1 < MIN_LINES {
+            continue;
+        }
+        let text = lines[start + 1..end]
+            .iter()
+            .map(|l| l.get(indent..).unwrap_or(""))
+            .collect::<Vec<_>>()
+            .join("\n");
+        blocks.push(Block {
+            key,
+            start,
+            end,
+            text,
+        });
+    }
+    blocks
+}
+
+/// Whether the value on this line, after any `- ` and key, is a block
+/// scalar whose content follows on the next lines.
+fn opens_block_scalar(mut trimmed: &str) -> bool {
+    while let Some(item) = trimmed.strip_prefix("- ") {
+        trimmed = item.trim_start();
+    }
+    let value = match split_key(trimmed) {
+        Some((_, rest)) => rest.trim_start(),
+        None => trimmed,
+    };
+    value.starts_with('|') || value.starts_with('>')
+}
+
+/// Anchor named after `key`, made unique among `used`.
+fn anchor_name(key: &str, used: &mut HashSet<String>) -> String {
+    let base: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    let base = if base.is_empty() {
+        "anchor".to_owned()
+    } else {
+        base
+    };
+    let mut name = base.clone();
+    let mut n = 2;
+    while !used.insert(name.clone()) {
+        name = format!("{}_{}", base, n);
+        n += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::share_repeated;
+
+    #[test]
+    fn repeated_subtrees() {
+        let yaml = "defaults:\n  retries: 3\n  timeout: 10\nprod:\n  db:\n    retries: 3\n    timeout: 10\n  hosts:\n  - a\n  - b\ndev:\n  db:\n    retries: 3\n    timeout: 10\n  hosts:\n  - a\n  - b\nsmall:\n  x: 1\nother:\n  x: 1\n";
+        assert_eq!(
+            share_repeated(yaml),
+            "defaults: &defaults\n  retries: 3\n  timeout: 10\nprod: &prod\n  db: *defaults\n  hosts:\n  - a\n  - b\ndev: *prod\nsmall:\n  x: 1\nother:\n  x: 1\n"
+        );
+    }
+
+    #[test]
+    fn nested_first_occurrence() {
+        let yaml = "a:\n  b:\n    x: 1\n    y: 2\n  c: 3\nd:\n  x: 1\n  y: 2\n";
+        assert_eq!(
+            share_repeated(yaml),
+            "a:\n  b: &b\n    x: 1\n    y: 2\n  c: 3\nd: *b\n"
+        );
+    }
+
+    #[test]
+    fn block_scalars_untouched() {
+        let yaml = "a:\n  x: 1\n  y: 2\nb: |\n  k:\n    x: 1\n    y: 2\n  tail\nc:\n- |\n  k:\n    x: 1\n    y: 2\n";
+        assert_eq!(share_repeated(yaml), yaml);
+
+        let yaml = "a: |\n  k:\n    x: 1\n    y: 2\nb:\n  x: 1\n  y: 2\nc:\n  x: 1\n  y: 2\n";
+        assert_eq!(
+            share_repeated(yaml),
+            "a: |\n  k:\n    x: 1\n    y: 2\nb: &b\n  x: 1\n  y: 2\nc: *b\n"
+        );
+    }
+}
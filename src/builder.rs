@@ -0,0 +1,97 @@
+//! Layered configuration assembled from several sources.
+
+use crate::{Config, Error, MergeStrategy};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::path::PathBuf;
+
+enum Source {
+    Defaults(Result<Value, Error>),
+    File { path: PathBuf, optional: bool },
+    Env(String),
+}
+
+/// Merges sources into a single `Config`, later sources taking precedence
+/// over earlier ones:
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # extern crate tuneup;
+/// # #[derive(Serialize, Default)]
+/// # struct Defaults {}
+/// # fn main() -> Result<(), tuneup::Error> {
+/// let config = tuneup::ConfigBuilder::new()
+///     .add_defaults(&Defaults::default())
+///     .add_file_optional("/etc/app/config.yaml")
+///     .add_file_optional("config.yaml")
+///     .add_env("APP")
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The built config uses the last added file as its own, so `write_to_file`
+/// saves to the most specific location.
+#[derive(Default)]
+pub struct ConfigBuilder {
+    sources: Vec<Source>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `value`, which has to serialize to a mapping, typically a struct
+    /// holding the built-in defaults.
+    pub fn add_defaults<T: Serialize>(mut self, value: &T) -> Self {
+        let value =
+            serde_yaml::to_value(value).map_err(|e| Error::SerializationFailed(e.to_string()));
+        self.sources.push(Source::Defaults(value));
+        self
+    }
+
+    /// Adds a file that has to exist when `build` is called.
+    pub fn add_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.sources.push(Source::File {
+            path: path.into(),
+            optional: false,
+        });
+        self
+    }
+
+    /// Adds a file that is skipped if it doesn't exist.
+    pub fn add_file_optional<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.sources.push(Source::File {
+            path: path.into(),
+            optional: true,
+        });
+        self
+    }
+
+    /// Adds environment overrides, see `Config::apply_env_overrides`.
+    pub fn add_env(mut self, prefix: &str) -> Self {
+        self.sources.push(Source::Env(prefix.to_owned()));
+        self
+    }
+
+    /// Loads and merges all sources in the order they were added.
+    pub fn build(self) -> Result<Config, Error> {
+        let mut config = Config::new();
+        for source in self.sources {
+            match source {
+                Source::Defaults(value) => config.merge_value(&value?, MergeStrategy::Overwrite)?,
+                Source::File { path, optional } => {
+                    config.file = Some(path.clone());
+                    if optional && !path.exists() {
+                        continue;
+                    }
+                    config.merge_file(path)?;
+                }
+                Source::Env(prefix) => config.apply_env_overrides(&prefix)?,
+            }
+        }
+        Ok(config)
+    }
+}
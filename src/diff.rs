@@ -0,0 +1,105 @@
+use crate::{path, Config, Error};
+use serde_yaml::{Mapping, Value};
+
+/// A difference between two configs, see `Config::diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The key is only set in the other config.
+    Added { path: String, value: Value },
+    /// The key is only set in this config.
+    Removed { path: String, value: Value },
+    /// The key is set in both configs to different values.
+    Modified {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl Change {
+    /// Dotted path of the changed key.
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Modified { path, .. } => path,
+        }
+    }
+}
+
+impl Config {
+    /// Lists the changes that turn this config into `other`.
+    ///
+    /// Mappings present on both sides are compared key by key, so a changed
+    /// leaf is reported with its full dotted path. Any other value, including
+    /// a sequence, is compared as a whole, and a subtree that only exists on
+    /// one side is a single `Added` or `Removed` change. Keys that are not
+    /// strings are skipped, as in `keys`.
+    pub fn diff(&self, other: &Config) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if let (Value::Mapping(old), Value::Mapping(new)) = (&self.root, &other.root) {
+            diff_mappings(&mut Vec::new(), old, new, self.separator, &mut changes);
+        }
+        changes
+    }
+
+    /// Lists the changes that turn the config file as it is on disk into
+    /// this config, i.e. what `write_to_file` would change. The file is read
+    /// with this config's settings into a separate config, so `self` is left
+    /// untouched. A missing file counts as empty, so every key is `Added`.
+    pub fn diff_file(&self) -> Result<Vec<Change>, Error> {
+        let mut on_disk = self.clone();
+        on_disk.autosave = false;
+        match on_disk.read_from_file() {
+            Ok(()) => {}
+            Err(Error::FileNotFound(_)) => on_disk.root = Value::Mapping(Mapping::new()),
+            Err(e) => return Err(e),
+        }
+        Ok(on_disk.diff(self))
+    }
+}
+
+fn diff_mappings<'a>(
+    prefix: &mut Vec<&'a str>,
+    old: &'a Mapping,
+    new: &'a Mapping,
+    sep: char,
+    changes: &mut Vec<Change>,
+) {
+    for (k, old_value) in old {
+        let key = match k.as_str() {
+            Some(k) => k,
+            None => continue,
+        };
+        prefix.push(key);
+        match (old_value, new.get(k)) {
+            (Value::Mapping(o), Some(Value::Mapping(n))) => {
+                diff_mappings(prefix, o, n, sep, changes)
+            }
+            (o, Some(n)) if o != n => changes.push(Change::Modified {
+                path: path::join(prefix, sep),
+                old: o.clone(),
+                new: n.clone(),
+            }),
+            (_, Some(_)) => {}
+            (o, None) => changes.push(Change::Removed {
+                path: path::join(prefix, sep),
+                value: o.clone(),
+            }),
+        }
+        prefix.pop();
+    }
+    for (k, new_value) in new {
+        match k.as_str() {
+            Some(key) if !old.contains_key(k) => {
+                prefix.push(key);
+                changes.push(Change::Added {
+                    path: path::join(prefix, sep),
+                    value: new_value.clone(),
+                });
+                prefix.pop();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Comments above keys, kept next to the tree because serde_yaml drops
+//! them. Serialized YAML is scanned line by line to find which key each
+//! line starts; keys inside sequences aren't tracked.
+
+use crate::{to_value, Config, Error};
+use serde::Serialize;
+use std::collections::HashMap;
+
+impl Config {
+    /// Stores `value` at `name`, replacing any existing value, and attaches
+    /// `doc` to it. YAML files get the doc
+    /// written as `#` lines above the key, and comment lines directly above a
+    /// key are read back as its doc, so docs survive a round trip through
+    /// the file. Other formats ignore docs.
+    pub fn add_documented<T: Serialize>(
+        &mut self,
+        name: &str,
+        value: T,
+        doc: &str,
+    ) -> Result<(), Error> {
+        let value = to_value(name, value)?;
+        self.insert_value(name, value)?;
+        self.docs.insert(name.to_owned(), doc.to_owned());
+        Ok(())
+    }
+
+    /// Doc attached to `name` with `add_documented` or read from the file.
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        self.docs.get(name).map(String::as_str)
+    }
+}
+
+/// Drops the docs of `path` and of the keys below it.
+pub(crate) fn remove(docs: &mut HashMap<String, String>, path: &str, sep: char) {
+    docs.retain(|key, _| !is_within(key, path, sep));
+}
+
+/// Moves the docs of `old` and of the keys below it to `new`.
+pub(crate) fn rename(docs: &mut HashMap<String, String>, old: &str, new: &str, sep: char) {
+    let moved: Vec<_> = docs
+        .keys()
+        .filter(|key| is_within(key, old, sep))
+        .cloned()
+        .collect();
+    for key in moved {
+        let doc = docs.remove(&key).unwrap();
+        docs.insert(format!("{}{}", new, &key[old.len()..]), doc);
+    }
+}
+
+/// Whether `key` is `path` or a key below it.
+fn is_within(key: &str, path: &str, sep: char) -> bool {
+    match key.strip_prefix(path) {
+        Some(rest) => rest.is_empty() || rest.starts_with(sep),
+        None => false,
+    }
+}
+
+/// Inserts the docs in `docs` above their keys in the serialized `yaml`.
+pub(crate) fn annotate(yaml: &str, docs: &HashMap<String, String>, sep: char) -> String {
+    let mut out = String::with_capacity(yaml.len());
+    let mut scanner = Scanner::new(sep);
+    for line in yaml.lines() {
+        if let Line::Key { path, indent } = scanner.next(line) {
+            if let Some(doc) = docs.get(&path) {
+                for doc_line in doc.lines() {
+                    out.extend(std::iter::repeat_n(' ', indent));
+                    match doc_line {
+                        "" => out.push('#'),
+                        l => {
+                            out.push_str("# ");
+                            out.push_str(l);
+                        }
+                    }
+                    out.push('\n');
+                }
+            }
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Comment blocks directly above a key, keyed by the key's path. A blank
+/// line between a comment and the key detaches it, as for a file header.
+pub(crate) fn extract(yaml: &str, sep: char) -> HashMap<String, String> {
+    let mut docs = HashMap::new();
+    let mut pending: Vec<&str> = Vec::new();
+    let mut scanner = Scanner::new(sep);
+    for line in yaml.lines() {
+        match scanner.next(line) {
+            Line::Comment(text) => pending.push(text),
+            Line::Key { path, .. } if !pending.is_empty() => {
+                docs.insert(path, pending.join("\n"));
+                pending.clear();
+            }
+            _ => pending.clear(),
+        }
+    }
+    docs
+}
+
+enum Line<'a> {
+    Comment(&'a str),
+    Key { path: String, indent: usize },
+    Other,
+}
+
+/// Tracks the mapping keys enclosing the current line.
+struct Scanner {
+    sep: char,
+    /// Indentation and key of each enclosing line, `None` for a sequence
+    /// item, below which paths aren't known.
+    parents: Vec<(usize, Option<String>)>,
+    /// Indentation of the key whose block scalar (`|` or `>`) is being read.
+    block: Option<usize>,
+}
+
+impl Scanner {
+    fn new(sep: char) -> Self {
+        Scanner {
+            sep,
+            parents: Vec::new(),
+            block: None,
+        }
+    }
+
+    fn next<'a>(&mut self, line: &'a str) -> Line<'a> {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some(block) = self.block {
+            if trimmed.is_empty() || indent > block {
+                return Line::Other;
+            }
+            self.block = None;
+        }
+        if trimmed.is_empty() {
+            return Line::Other;
+        }
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            return Line::Comment(comment.strip_prefix(' ').unwrap_or(comment));
+        }
+        while self.parents.last().is_some_and(|&(i, _)| i >= indent) {
+            self.parents.pop();
+        }
+        if trimmed == "-" || trimmed.starts_with("- ") {
+            self.parents.push((indent, None));
+            return Line::Other;
+        }
+        let (key, rest) = match split_key(trimmed) {
+            Some(split) => split,
+            None => return Line::Other,
+        };
+        let rest = rest.trim_start();
+        if rest.starts_with('|') || rest.starts_with('>') {
+            self.block = Some(indent);
+        }
+        let mut path = String::new();
+        for (_, parent) in &self.parents {
+            match parent {
+                Some(parent) => {
+                    path.push_str(parent);
+                    path.push(self.sep);
+                }
+                None => {
+                    self.parents.push((indent, None));
+                    return Line::Other;
+                }
+            }
+        }
+        path.push_str(&key);
+        self.parents.push((indent, Some(key)));
+        Line::Key { path, indent }
+    }
+}
+
+/// Splits `key: value` into the unquoted key and the rest of the line.
+pub(crate) fn split_key(s: &str) -> Option<(String, &str)> {
+    let (key, rest) = match s.chars().next()? {
+        '\'' => {
+            let end = closing_quote(s, '\'')?;
+            (s[1..end].replace("''", "'"), &s[end + 1..])
+        }
+        '"' => {
+            let end = closing_quote(s, '"')?;
+            let key = s[1..end].replace("\\\"", "\"").replace("\\\\", "\\");
+            (key, &s[end + 1..])
+        }
+        '?' | '{' | '[' => return None,
+        _ => {
+            let end = s.find(": ").or_else(|| s.strip_suffix(':').map(str::len))?;
+            (s[..end].to_owned(), &s[end..])
+        }
+    };
+    let rest = rest.strip_prefix(':')?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some((key, rest))
+    } else {
+        None
+    }
+}
+
+/// Byte index of the quote closing the quoted string at the start of `s`.
+fn closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut chars = s.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            c if c == quote => {
+                if quote == '\'' && chars.peek().map(|&(_, c)| c) == Some('\'') {
+                    chars.next();
+                } else {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{annotate, extract};
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_trip() {
+        let yaml = "db:\n  host: localhost\n  'the port': 5432\nhosts:\n- name: a\n  port: 1\nmotd: |\n  port: not a key\nport: 1\n";
+        let mut docs = HashMap::new();
+        docs.insert("db.the port".to_owned(), "Port to\n\nconnect to".to_owned());
+        docs.insert("port".to_owned(), "top-level".to_owned());
+        docs.insert("hosts.name".to_owned(), "never written".to_owned());
+
+        let annotated = annotate(yaml, &docs, '.');
+        assert_eq!(
+            annotated,
+            "db:\n  host: localhost\n  # Port to\n  #\n  # connect to\n  'the port': 5432\nhosts:\n- name: a\n  port: 1\nmotd: |\n  port: not a key\n# top-level\nport: 1\n"
+        );
+        docs.remove("hosts.name");
+        assert_eq!(extract(&annotated, '.'), docs);
+    }
+
+    #[test]
+    fn detached_comments() {
+        let yaml = "# header\n\na: 1\n# trailing\n";
+        assert!(extract(yaml, '.').is_empty());
+    }
+}
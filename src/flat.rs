@@ -0,0 +1,124 @@
+use crate::{path, Config, Error, PATH_SEPARATOR};
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+impl Config {
+    /// Flattens the config into leaf values keyed by dotted path, e.g.
+    /// `db.connection.port`. Sequence elements are keyed by index, as in
+    /// `servers.0`. Empty mappings and sequences are kept as leaves, and
+    /// keys that are not strings are skipped, as in `keys`.
+    pub fn flatten(&self) -> HashMap<String, Value> {
+        let mut flat = HashMap::new();
+        if let Value::Mapping(m) = &self.root {
+            if !m.is_empty() {
+                flatten_into(&mut Vec::new(), &self.root, self.separator, &mut flat);
+            }
+        }
+        flat
+    }
+
+    /// Flattens the config into environment variables for a child process,
+    /// sorted by name: `{ db: { port: 5432 } }` with prefix `APP` yields
+    /// `APP_DB_PORT=5432`. Keys are uppercased and joined with `_`, so unlike
+    /// `apply_env_overrides`, which takes `__` between segments, `db_port`
+    /// and `db.port` would both be `APP_DB_PORT`. Keys that end up with the
+    /// same name, including ones that differ only by case, fail with
+    /// `EnvVarCollision` instead of one silently replacing the other.
+    /// Sequence elements are keyed by index, as in `APP_HOSTS_0`.
+    ///
+    /// Strings are exported as they are and other scalars in their YAML
+    /// form, except null, which becomes an empty string. Empty mappings and
+    /// sequences, the only leaves that aren't scalars, become `{}` and `[]`.
+    pub fn export_env(&self, prefix: &str) -> Result<Vec<(String, String)>, Error> {
+        let prefix = prefix.trim_end_matches('_');
+        let mut flat: Vec<_> = self.flatten().into_iter().collect();
+        flat.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut vars: HashMap<String, (String, String)> = HashMap::new();
+        for (key, v) in flat {
+            let mut name = key.replace(self.separator, "_");
+            if !prefix.is_empty() {
+                name = format!("{}_{}", prefix, name);
+            }
+            let name = name.to_uppercase();
+            if let Some((first, _)) = vars.get(&name) {
+                return Err(Error::EnvVarCollision {
+                    name,
+                    keys: (first.clone(), key),
+                });
+            }
+            let value = match v {
+                Value::String(s) => s,
+                Value::Null => String::new(),
+                v => serde_yaml::to_string(&v)
+                    .map(|s| s.trim_end().to_owned())
+                    .unwrap_or_default(),
+            };
+            vars.insert(name, (key, value));
+        }
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .map(|(name, (_, value))| (name, value))
+            .collect();
+        vars.sort();
+        Ok(vars)
+    }
+
+    /// Rebuilds a config from the output of `flatten`. A mapping whose keys
+    /// are exactly `0` to `n - 1` becomes a sequence again. Keys are split on
+    /// the default `PATH_SEPARATOR`.
+    pub fn from_flat<I>(entries: I) -> Result<Config, Error>
+    where
+        I: IntoIterator<Item = (String, Value)>,
+    {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut config = Config::new();
+        for (key, value) in entries {
+            path::insert(&mut config.root, &key, value, PATH_SEPARATOR)?;
+        }
+        restore_sequences(&mut config.root);
+        Ok(config)
+    }
+}
+
+fn flatten_into(prefix: &mut Vec<String>, v: &Value, sep: char, flat: &mut HashMap<String, Value>) {
+    match v {
+        Value::Mapping(m) if !m.is_empty() => {
+            for (k, item) in m {
+                if let Some(k) = k.as_str() {
+                    prefix.push(k.to_owned());
+                    flatten_into(prefix, item, sep, flat);
+                    prefix.pop();
+                }
+            }
+        }
+        Value::Sequence(seq) if !seq.is_empty() => {
+            for (i, item) in seq.iter().enumerate() {
+                prefix.push(i.to_string());
+                flatten_into(prefix, item, sep, flat);
+                prefix.pop();
+            }
+        }
+        v => {
+            let segments: Vec<&str> = prefix.iter().map(String::as_str).collect();
+            flat.insert(path::join(&segments, sep), v.clone());
+        }
+    }
+}
+
+fn restore_sequences(v: &mut Value) {
+    if let Value::Mapping(m) = v {
+        for (_, item) in m.iter_mut() {
+            restore_sequences(item);
+        }
+        let is_sequence =
+            !m.is_empty() && (0..m.len()).all(|i| m.contains_key(Value::from(i.to_string())));
+        if is_sequence {
+            let mut m = std::mem::take(m);
+            let seq = (0..m.len())
+                .map(|i| m.remove(i.to_string()).unwrap())
+                .collect();
+            *v = Value::Sequence(seq);
+        }
+    }
+}
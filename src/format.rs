@@ -0,0 +1,75 @@
+use crate::{json, toml, Error};
+use serde_yaml::Value;
+use std::path::Path;
+
+/// On-disk representation of a config file.
+///
+/// Whatever the format, the config is kept in memory as a YAML `Value`.
+/// JSON and TOML map onto it, so documents are converted on read and
+/// write instead of switching the internal tree to another value type.
+/// TOML requires a table at the root and has no null, so keys set to null
+/// are left out when writing TOML, and a null inside a sequence fails with
+/// `SerializationFailed`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// Guesses the format from the file extension, falling back to YAML.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        Self::from_extension(path).unwrap_or(Format::Yaml)
+    }
+
+    /// Format implied by a known extension, `None` for any other.
+    pub(crate) fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?;
+        [
+            ("yaml", Format::Yaml),
+            ("yml", Format::Yaml),
+            ("json", Format::Json),
+            ("toml", Format::Toml),
+        ]
+        .iter()
+        .find(|(e, _)| ext.eq_ignore_ascii_case(e))
+        .map(|&(_, f)| f)
+    }
+
+    pub(crate) fn parse(self, s: &str) -> Result<Value, Error> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(s).map_err(|e| e.to_string()),
+            Format::Json => json::from_str(s),
+            Format::Toml => toml::from_str(s),
+        }
+        .map_err(|message| Error::DeserializationFailed {
+            key: String::new(),
+            message,
+        })
+    }
+
+    /// `text` as a comment block ending in a newline, `None` for JSON.
+    pub(crate) fn comment(self, text: &str) -> Option<String> {
+        if self == Format::Json {
+            return None;
+        }
+        let lines = text.lines().map(|l| match l {
+            "" => "#\n".to_owned(),
+            l => format!("# {}\n", l),
+        });
+        Some(lines.collect())
+    }
+
+    /// `pretty` only affects JSON; YAML and TOML are always written in
+    /// block style.
+    pub(crate) fn serialize(self, v: &Value, pretty: bool) -> Result<String, Error> {
+        match self {
+            Format::Yaml => serde_yaml::to_string(v).map_err(|e| e.to_string()),
+            Format::Json if pretty => json::to_string(v),
+            Format::Json => json::to_string_compact(v),
+            Format::Toml => toml::to_string(v),
+        }
+        .map_err(Error::SerializationFailed)
+    }
+}
@@ -0,0 +1,124 @@
+//! `${VAR}` / `$VAR` expansion inside string values.
+
+use crate::Error;
+use serde_yaml::Value;
+
+/// Expands variables in every string scalar below `v`.
+pub(crate) fn expand_value<F>(v: &mut Value, strict: bool, lookup: &F) -> Result<(), Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match v {
+        Value::String(s) => *s = expand(s, strict, lookup)?,
+        Value::Sequence(seq) => {
+            for item in seq {
+                expand_value(item, strict, lookup)?;
+            }
+        }
+        Value::Mapping(m) => {
+            for (_, item) in m.iter_mut() {
+                expand_value(item, strict, lookup)?;
+            }
+        }
+        Value::Tagged(t) => expand_value(&mut t.value, strict, lookup)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${VAR}` and `$VAR` in `s` and turns `$$` into `$`. Unknown
+/// variables are kept as written unless `strict` is set. A `$` that doesn't
+/// start a variable, e.g. in `$5` or an unterminated `${`, is left alone.
+fn expand<F>(s: &str, strict: bool, lookup: &F) -> Result<String, Error>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (name, len) = if rest.starts_with('$') {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) if is_name(&braced[..end]) => (&braced[..end], end + 2),
+                _ => ("", 0),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if is_name(&rest[..end]) {
+                (&rest[..end], end)
+            } else {
+                ("", 0)
+            }
+        };
+        if len == 0 {
+            out.push('$');
+            continue;
+        }
+        match lookup(name) {
+            Some(value) => out.push_str(&value),
+            None if strict => return Err(Error::UndefinedEnvVar(name.to_owned())),
+            None => {
+                out.push('$');
+                out.push_str(&rest[..len]);
+            }
+        }
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use crate::Error;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("example.com".to_owned()),
+            "PORT" => Some("8080".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands() {
+        assert_eq!(
+            expand("https://${HOST}:$PORT/x", false, &lookup).unwrap(),
+            "https://example.com:8080/x"
+        );
+        assert_eq!(
+            expand("$$HOST costs $5", false, &lookup).unwrap(),
+            "$HOST costs $5"
+        );
+        assert_eq!(expand("${HOST", false, &lookup).unwrap(), "${HOST");
+        assert_eq!(
+            expand("$NOPE ${NOPE}", false, &lookup).unwrap(),
+            "$NOPE ${NOPE}"
+        );
+    }
+
+    #[test]
+    fn strict() {
+        assert_eq!(
+            expand("${HOST}/${NOPE}", true, &lookup),
+            Err(Error::UndefinedEnvVar("NOPE".to_owned()))
+        );
+    }
+}
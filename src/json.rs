@@ -0,0 +1,487 @@
+//! Minimal JSON reader and writer working directly on `serde_yaml::Value`,
+//! so JSON support doesn't need a second value type or another dependency.
+
+use crate::{Config, Error};
+use serde_yaml::{Mapping, Number, Value};
+use std::fmt::Write;
+
+impl Config {
+    /// Value at `name` as compact JSON text, ready for
+    /// `serde_json::from_str` or any other JSON consumer. YAML-only
+    /// constructs are converted as when writing a JSON file: scalar mapping
+    /// keys become strings (`1: x` gives `{"1":"x"}`) and tags are dropped.
+    /// Mapping keys that are sequences or mappings, NaN and infinities fail
+    /// with `SerializationFailed`.
+    pub fn get_json_string(&self, name: &str) -> Result<String, Error> {
+        let v: Value = self.get(name)?;
+        to_string_compact(&v).map_err(Error::SerializationFailed)
+    }
+}
+
+pub fn from_str(s: &str) -> Result<Value, String> {
+    let mut p = Parser {
+        src: s,
+        bytes: s.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    p.skip_whitespace();
+    let v = p.parse_value()?;
+    p.skip_whitespace();
+    if p.pos != p.bytes.len() {
+        return Err(p.error("trailing characters"));
+    }
+    Ok(v)
+}
+
+/// Pretty-prints `v` with two-space indentation.
+pub fn to_string(v: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, v, Some(0))?;
+    Ok(out)
+}
+
+/// Writes `v` on a single line without any whitespace.
+pub fn to_string_compact(v: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, v, None)?;
+    Ok(out)
+}
+
+/// Deepest nesting of arrays and objects accepted, as in serde_json, so a
+/// hostile file fails to parse instead of overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> String {
+        let consumed = &self.src[..self.pos];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed.len() - consumed.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        format!("{} at line {} column {}", msg, line, column)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), String> {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", b as char)))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::parse_object),
+            Some(b'[') => self.nested(Self::parse_array),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => {
+                for (word, v) in &[
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                    ("null", Value::Null),
+                ] {
+                    if self.src[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(v.clone());
+                    }
+                }
+                Err(self.error("expected value"))
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Value, String>
+    where
+        F: FnOnce(&mut Self) -> Result<Value, String>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let v = parse(self);
+        self.depth -= 1;
+        v
+    }
+
+    fn parse_object(&mut self) -> Result<Value, String> {
+        self.expect(b'{')?;
+        let mut m = Mapping::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Mapping(m));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+            m.insert(Value::String(key), value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Mapping(m));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        self.expect(b'[')?;
+        let mut seq = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Sequence(seq));
+        }
+        loop {
+            self.skip_whitespace();
+            seq.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Sequence(seq));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            out.push_str(&self.src[start..self.pos]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            out.push(self.parse_unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    out.push(escaped);
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Parses the `XXXX` after `\u`, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.src[self.pos..].starts_with("\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let low = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        let code =
+            u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    /// Skips ASCII digits, returning how many there were.
+    fn skip_digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    /// Parses a number as RFC 8259 defines it: no leading zeros, and at
+    /// least one digit in the integer part, after `.` and in an exponent.
+    fn parse_number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let invalid = |p: &mut Self| {
+            p.pos = start;
+            Err(p.error("invalid number"))
+        };
+        let mut is_float = false;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        let int_digits = self.skip_digits();
+        if int_digits == 0 || (int_digits > 1 && self.bytes[int_start] == b'0') {
+            return invalid(self);
+        }
+        if self.peek() == Some(b'.') {
+            is_float = true;
+            self.pos += 1;
+            if self.skip_digits() == 0 {
+                return invalid(self);
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            is_float = true;
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.skip_digits() == 0 {
+                return invalid(self);
+            }
+        }
+        let text = &self.src[start..self.pos];
+        if !is_float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(Value::Number(i.into()));
+            }
+            if let Ok(u) = text.parse::<u64>() {
+                return Ok(Value::Number(u.into()));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Value::Number(f.into())),
+            _ => invalid(self),
+        }
+    }
+}
+
+/// `indent` is the current nesting depth, or `None` for compact output.
+fn write_value(out: &mut String, v: &Value, indent: Option<usize>) -> Result<(), String> {
+    let inner = indent.map(|i| i + 1);
+    match v {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(out, n)?,
+        Value::String(s) => write_string(out, s),
+        Value::Sequence(seq) => {
+            if seq.is_empty() {
+                out.push_str("[]");
+                return Ok(());
+            }
+            out.push('[');
+            for (i, item) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, inner);
+                write_value(out, item, inner)?;
+            }
+            newline(out, indent);
+            out.push(']');
+        }
+        Value::Mapping(m) => {
+            if m.is_empty() {
+                out.push_str("{}");
+                return Ok(());
+            }
+            out.push('{');
+            for (i, (k, item)) in m.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, inner);
+                write_string(out, &key_to_string(k)?);
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write_value(out, item, inner)?;
+            }
+            newline(out, indent);
+            out.push('}');
+        }
+        Value::Tagged(t) => write_value(out, &t.value, indent)?,
+    }
+    Ok(())
+}
+
+fn newline(out: &mut String, indent: Option<usize>) {
+    if let Some(indent) = indent {
+        out.push('\n');
+        for _ in 0..indent {
+            out.push_str("  ");
+        }
+    }
+}
+
+/// JSON object keys must be strings; scalar YAML keys are stringified.
+fn key_to_string(k: &Value) -> Result<String, String> {
+    match k {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("JSON object key must be a scalar, found {:?}", k)),
+    }
+}
+
+fn write_number(out: &mut String, n: &Number) -> Result<(), String> {
+    if let Some(i) = n.as_i64() {
+        write!(out, "{}", i).unwrap();
+    } else if let Some(u) = n.as_u64() {
+        write!(out, "{}", u).unwrap();
+    } else {
+        let f = n.as_f64().unwrap();
+        if !f.is_finite() {
+            return Err(format!("{} can't be represented in JSON", n));
+        }
+        // Debug keeps a fractional part, so the value reads back as a float.
+        write!(out, "{:?}", f).unwrap();
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_str, to_string, to_string_compact};
+    use serde_yaml::Value;
+
+    #[test]
+    fn round_trip() {
+        let input = r#"{"a": 1, "b": [true, null, -2.5e3], "c": {"d": "x\"\n\u00e9\ud83d\ude00"}}"#;
+        let v = from_str(input).unwrap();
+        assert_eq!(v["a"], Value::from(1));
+        assert_eq!(v["b"][2], Value::from(-2500.0));
+        assert_eq!(v["c"]["d"], Value::from("x\"\n\u{e9}\u{1f600}"));
+        assert_eq!(from_str(&to_string(&v).unwrap()).unwrap(), v);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(from_str("{\"a\": }").is_err());
+        assert!(from_str("[1, 2").is_err());
+        assert!(from_str("{} x").is_err());
+        assert!(from_str("\"\\q\"").is_err());
+        for number in &[
+            "01", "-01", "1.", "1.e3", ".5", "-", "1e", "1e+", "+1", "0x1",
+        ] {
+            assert!(from_str(number).is_err(), "accepted {}", number);
+        }
+        assert!(from_str(r#""\u+041""#).is_err());
+        assert!(from_str(r#""\u04 1""#).is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        for (text, v) in &[
+            ("0", Value::from(0)),
+            ("-0", Value::from(0)),
+            ("10", Value::from(10)),
+            ("0.5", Value::from(0.5)),
+            ("-1.25e2", Value::from(-125.0)),
+            ("1E-2", Value::from(0.01)),
+            ("18446744073709551615", Value::from(u64::MAX)),
+        ] {
+            assert_eq!(from_str(text).as_ref(), Ok(v), "{}", text);
+        }
+        assert_eq!(from_str(r#""\u0041""#), Ok(Value::from("A")));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(from_str(&nested(128)).is_ok());
+        assert_eq!(
+            from_str(&nested(129)),
+            Err("nesting too deep at line 1 column 129".to_owned())
+        );
+        assert!(from_str(&"[".repeat(400_000)).is_err());
+        assert!(from_str(&"{\"a\":".repeat(200)).is_err());
+    }
+
+    #[test]
+    fn pretty_output() {
+        let v = from_str(r#"{"a": [1, 2.0], "b": {}}"#).unwrap();
+        assert_eq!(
+            to_string(&v).unwrap(),
+            "{\n  \"a\": [\n    1,\n    2.0\n  ],\n  \"b\": {}\n}"
+        );
+    }
+
+    #[test]
+    fn compact_output() {
+        let v = from_str(r#"{"a": [1, 2.0], "b": {"c": "d"}}"#).unwrap();
+        assert_eq!(
+            to_string_compact(&v).unwrap(),
+            r#"{"a":[1,2.0],"b":{"c":"d"}}"#
+        );
+        assert_eq!(from_str(&to_string_compact(&v).unwrap()).unwrap(), v);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{Config, Error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A config key bound to the type of its value, so both are declared once:
+///
+/// ```
+/// use tuneup::{Config, Key};
+///
+/// const PORT: Key<u16> = Key::new("server.port");
+///
+/// let mut c = Config::new();
+/// c.set_key(PORT, 8080).unwrap();
+/// assert_eq!(c.get_key(PORT).unwrap(), 8080);
+/// ```
+pub struct Key<T> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Key<T> {}
+
+impl<T> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Key").field(&self.name).finish()
+    }
+}
+
+impl Config {
+    pub fn get_key<T>(&self, key: Key<T>) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.get(key.name)
+    }
+
+    pub fn set_key<T>(&mut self, key: Key<T>, value: T) -> Result<Option<T>, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.add(key.name, value)
+    }
+}
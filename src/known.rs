@@ -0,0 +1,68 @@
+//! Misspelled top-level keys, found by comparing the config against the
+//! fields of a struct.
+
+use crate::Config;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+
+impl Config {
+    /// Top-level keys that `T` has no field for, in file order, e.g. `prot`
+    /// when `T` has a `port` field. serde ignores such keys unless
+    /// `T` is marked `deny_unknown_fields`, so a typo silently falls back to
+    /// the field's default instead.
+    ///
+    /// Field names are taken from `T`'s `Deserialize` impl, so renames and
+    /// aliases are honored. Types that aren't plain structs, including
+    /// structs with a `#[serde(flatten)]` field, accept any key and never
+    /// report one. Keys that aren't strings are ignored.
+    pub fn check_known_keys<T: DeserializeOwned>(&self) -> Result<(), Vec<String>> {
+        let fields = match field_names::<T>() {
+            Some(fields) => fields,
+            None => return Ok(()),
+        };
+        let unknown: Vec<String> = self
+            .keys()
+            .into_iter()
+            .filter(|k| !fields.contains(&k.as_str()))
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(unknown)
+        }
+    }
+}
+
+/// The field names a struct passes to `deserialize_struct`, `None` if `T`
+/// deserializes from something else.
+fn field_names<T: DeserializeOwned>() -> Option<&'static [&'static str]> {
+    let mut fields = None;
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Deserializer that only records the fields it's asked for and then fails.
+struct FieldNames<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'a, 'de> Deserializer<'de> for FieldNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(de::Error::custom("field names recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
@@ -1,142 +1,3742 @@
-#![feature(inner_deref)]
 #![allow(dead_code)]
 
+#[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
 extern crate serde_yaml;
 
+/// Reports a `TraceEvent` to the hook; compiles to nothing without the
+/// `trace` feature.
+macro_rules! trace_event {
+    ($($event:tt)*) => {
+        #[cfg(feature = "trace")]
+        crate::trace::emit(crate::trace::TraceEvent::$($event)*);
+    };
+}
+
+mod anchors;
+mod base64;
+mod builder;
+mod diff;
+mod docs;
+mod flat;
+mod format;
+mod interpolate;
+mod json;
+mod key;
+mod known;
+mod merge;
+mod migrate;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod path;
+#[cfg(feature = "schema")]
+mod schema;
+mod toml;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use builder::ConfigBuilder;
+pub use diff::Change;
+pub use format::Format;
+pub use key::Key;
+pub use merge::MergeStrategy;
+pub use migrate::Migration;
+#[cfg(feature = "schema")]
+pub use schema::ValidationError;
+#[cfg(feature = "trace")]
+pub use trace::{set_trace_hook, TraceEvent};
+#[cfg(feature = "watch")]
+pub use watch::WatchHandle;
+
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use serde_yaml::value::Tag;
 use serde_yaml::{Mapping, Value};
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default separator between segments of a nested key, e.g.
+/// `database.connection.port`. See `Config::with_path_separator`.
+pub const PATH_SEPARATOR: char = '.';
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     SerializationFailed(String),
-    ConfigDoesNotEsixt,
-    DeserializationFailed(String),
-    FileOpenFailed(String),
-    FileDoesNotSet,
+    KeyNotFound(String),
+    /// A value doesn't fit the requested type, or a document doesn't parse.
+    /// `key` is the config key being read, empty for a whole document.
+    /// Parse errors carry serde_yaml's line and column in the message.
+    DeserializationFailed {
+        key: String,
+        message: String,
+    },
+    FileOpenFailed {
+        path: String,
+        source: String,
+    },
+    /// The file to read doesn't exist, which is normal on a first run.
+    FileNotFound(String),
+    FileNotSet,
+    /// Value at the given path exists but is not a mapping, so it can't be descended into.
+    NotAMapping(String),
+    /// A path segment indexes past the end of a sequence.
+    IndexOutOfBounds(String),
+    /// `push` found a value that is not a sequence at the given path.
+    NotASequence(String),
+    /// `try_add` found a value already stored under the key.
+    KeyExists(String),
+    /// The document's top level is not a mapping, e.g. a list or a scalar.
+    RootNotMapping,
+    /// Reading from or writing to a stream failed.
+    Io(String),
+    /// `expand_env_strict` found a reference to an unset variable.
+    UndefinedEnvVar(String),
+    /// The file is bigger than the limit set with `with_max_size`.
+    FileTooLarge {
+        path: String,
+        limit: u64,
+    },
+    /// `migrate` found a config version newer than any migration targets.
+    ConfigVersionTooNew {
+        found: u32,
+        supported: u32,
+    },
+    /// None of the paths set with `with_search_paths` exists.
+    NoConfigFound(Vec<String>),
+    /// The format set with `with_format` disagrees with the file extension
+    /// and `with_strict_extension` is on.
+    ExtensionFormatMismatch {
+        path: String,
+        format: Format,
+    },
+    /// The lock set up with `with_file_lock_timeout` wasn't acquired in time.
+    LockTimeout(String),
+    /// The config was made read-only with `with_readonly`.
+    ReadOnly,
+    /// A command line override isn't of the form `key=value`.
+    MalformedOverride(String),
+    /// A key read with `required` isn't set.
+    RequiredKeyMissing(String),
+    /// `export_env` would give two config keys the same variable name.
+    EnvVarCollision {
+        name: String,
+        keys: (String, String),
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SerializationFailed(e) => write!(f, "failed to serialize config: {}", e),
+            Error::KeyNotFound(key) => write!(f, "config key `{}` not found", key),
+            Error::DeserializationFailed { key, message } if key.is_empty() => {
+                write!(f, "failed to deserialize config: {}", message)
+            }
+            Error::DeserializationFailed { key, message } => {
+                write!(f, "failed to deserialize config key `{}`: {}", key, message)
+            }
+            Error::FileOpenFailed { path, source } => {
+                write!(f, "failed to access config file `{}`: {}", path, source)
+            }
+            Error::FileNotFound(path) => write!(f, "config file `{}` not found", path),
+            Error::FileNotSet => write!(f, "config file path is not set"),
+            Error::NotAMapping(path) => write!(f, "config value at `{}` is not a mapping", path),
+            Error::IndexOutOfBounds(path) => write!(f, "config index `{}` is out of bounds", path),
+            Error::NotASequence(path) => write!(f, "config value at `{}` is not a sequence", path),
+            Error::KeyExists(key) => write!(f, "config key `{}` already exists", key),
+            Error::RootNotMapping => write!(f, "config root is not a mapping"),
+            Error::Io(e) => write!(f, "config I/O failed: {}", e),
+            Error::UndefinedEnvVar(var) => {
+                write!(f, "environment variable `{}` is not set", var)
+            }
+            Error::FileTooLarge { path, limit } => {
+                write!(f, "config file `{}` exceeds {} bytes", path, limit)
+            }
+            Error::ConfigVersionTooNew { found, supported } => write!(
+                f,
+                "config version {} is newer than the supported version {}",
+                found, supported
+            ),
+            Error::NoConfigFound(tried) => {
+                write!(f, "no config file found, tried ")?;
+                for (i, path) in tried.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}`{}`", sep, path)?;
+                }
+                Ok(())
+            }
+            Error::ExtensionFormatMismatch { path, format } => write!(
+                f,
+                "config file `{}` doesn't have a {:?} extension",
+                path, format
+            ),
+            Error::LockTimeout(path) => {
+                write!(
+                    f,
+                    "timed out waiting for the lock on config file `{}`",
+                    path
+                )
+            }
+            Error::ReadOnly => write!(f, "config is read-only"),
+            Error::MalformedOverride(arg) => {
+                write!(f, "override `{}` is not of the form key=value", arg)
+            }
+            Error::RequiredKeyMissing(key) => {
+                write!(f, "required config key `{}` is missing", key)
+            }
+            Error::EnvVarCollision { name, keys } => write!(
+                f,
+                "config keys `{}` and `{}` both export as `{}`",
+                keys.0, keys.1, name
+            ),
+        }
+    }
+}
+
+// Underlying serde and io errors are kept as messages so that `Error` stays
+// `PartialEq`, hence there is no `source()` to expose.
+impl std::error::Error for Error {}
+
+/// What `Config::load_or_create` did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadOutcome {
+    /// The file existed and was read.
+    Loaded,
+    /// The file didn't exist and was written from the defaults.
+    Created,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    root: Value,
+    file: Option<PathBuf>,
+    format: Option<Format>,
+    atomic_write: bool,
+    create_dirs: bool,
+    pretty: bool,
+    sorted_keys: bool,
+    trailing_newline: bool,
+    max_size: Option<u64>,
+    dirty: bool,
+    autosave: bool,
+    version_key: String,
+    search_paths: Vec<PathBuf>,
+    separator: char,
+    case_insensitive: bool,
+    strict_extension: bool,
+    header_comment: Option<String>,
+    lenient_scalars: bool,
+    /// Modification time and size of the file when last read or written.
+    file_stamp: Option<(SystemTime, u64)>,
+    readonly: bool,
+    file_lock: bool,
+    lock_timeout: Option<Duration>,
+    anchors: bool,
+    /// Comments attached to keys, see `add_documented`.
+    docs: HashMap<String, String>,
+}
+
+/// Configs are equal if they hold the same values and settings. Whether
+/// there are unsaved changes, when the file was last read and the comments
+/// kept for keys are left out, so a config equals its clone after a change
+/// is undone or the clone is saved.
+impl PartialEq for Config {
+    fn eq(&self, other: &Self) -> bool {
+        // Destructured so that a new field has to be sorted in or out here.
+        let Config {
+            root,
+            file,
+            format,
+            atomic_write,
+            create_dirs,
+            pretty,
+            sorted_keys,
+            trailing_newline,
+            max_size,
+            autosave,
+            version_key,
+            search_paths,
+            separator,
+            case_insensitive,
+            strict_extension,
+            header_comment,
+            lenient_scalars,
+            readonly,
+            file_lock,
+            lock_timeout,
+            anchors,
+            dirty: _,
+            file_stamp: _,
+            docs: _,
+        } = self;
+        root == &other.root
+            && file == &other.file
+            && format == &other.format
+            && atomic_write == &other.atomic_write
+            && create_dirs == &other.create_dirs
+            && pretty == &other.pretty
+            && sorted_keys == &other.sorted_keys
+            && trailing_newline == &other.trailing_newline
+            && max_size == &other.max_size
+            && autosave == &other.autosave
+            && version_key == &other.version_key
+            && search_paths == &other.search_paths
+            && separator == &other.separator
+            && case_insensitive == &other.case_insensitive
+            && strict_extension == &other.strict_extension
+            && header_comment == &other.header_comment
+            && lenient_scalars == &other.lenient_scalars
+            && readonly == &other.readonly
+            && file_lock == &other.file_lock
+            && lock_timeout == &other.lock_timeout
+            && anchors == &other.anchors
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Config::from_str(s)
+    }
+}
+
+impl TryFrom<&str> for Config {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Error> {
+        Config::from_str(s)
+    }
+}
+
+impl TryFrom<String> for Config {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        Config::from_str(&s)
+    }
+}
+
+impl Drop for Config {
+    fn drop(&mut self) {
+        if self.autosave && self.dirty && !self.readonly {
+            if let Err(_e) = self.write_to_file() {
+                trace_event!(SaveOnDropFailed { error: &_e });
+            }
+        }
+    }
 }
 
-#[derive(Default)]
-pub struct Config {
-    root: Value,
-    file: Option<String>,
-}
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            root: Value::Mapping(Mapping::new()),
+            file: None,
+            format: None,
+            atomic_write: true,
+            create_dirs: true,
+            pretty: true,
+            sorted_keys: false,
+            trailing_newline: true,
+            max_size: None,
+            dirty: false,
+            autosave: false,
+            version_key: "version".to_owned(),
+            search_paths: Vec::new(),
+            separator: PATH_SEPARATOR,
+            case_insensitive: false,
+            strict_extension: false,
+            header_comment: None,
+            lenient_scalars: false,
+            file_stamp: None,
+            readonly: false,
+            file_lock: false,
+            lock_timeout: None,
+            anchors: false,
+            docs: HashMap::new(),
+        }
+    }
+
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// The configured file path, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Uses `sep` instead of `.` between the segments of nested keys, e.g.
+    /// to address keys that contain dots such as domain names. This applies to
+    /// every method taking a key path on this config, to the `__` mapping of
+    /// environment overrides, and to the paths reported by `diff`, `flatten`
+    /// and `validate_schema`.
+    pub fn with_path_separator(mut self, sep: char) -> Self {
+        self.separator = sep;
+        self
+    }
+
+    /// Makes `read_from_file` and `write_to_file` lock the sibling file
+    /// `<file>.lock`, so writers from several processes take turns and
+    /// readers never see a half-written file. The lock is advisory: it only
+    /// keeps out processes that use it too. By default it is waited for
+    /// indefinitely, see `with_file_lock_timeout`.
+    pub fn with_file_lock(mut self, lock: bool) -> Self {
+        self.file_lock = lock;
+        self
+    }
+
+    /// Turns on `with_file_lock` and gives up waiting for the lock after
+    /// `timeout` with `LockTimeout`.
+    pub fn with_file_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.file_lock = true;
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// YAML anchors and aliases are expanded when a file is read, so by
+    /// default a subtree shared with `&name` / `*name` is written back out
+    /// in full wherever it was used. Pass `true` to write the value of any
+    /// key that repeats an earlier key's value, at least two lines long, as
+    /// an alias of it instead. Anchors are named after the first key, so the
+    /// original names only survive if they matched. Merge keys (`<<`) are
+    /// not applied on read and are kept as ordinary `<<` keys.
+    pub fn with_anchors(mut self, anchors: bool) -> Self {
+        self.anchors = anchors;
+        self
+    }
+
+    /// Makes the config read-only, e.g. for a file managed by deployment
+    /// tooling: `write_to_file` and the methods that change values fail
+    /// with `ReadOnly`, and autosave is skipped. Reading, including
+    /// `reload`, still works. `merge`, `merge_with` and `clear` have no way
+    /// to report the error and leave a read-only config unchanged.
+    pub fn with_readonly(mut self, readonly: bool) -> Self {
+        self.readonly = readonly;
+        self
+    }
+
+    /// Lets `get` and the other read methods parse a string into the number
+    /// or boolean asked for, so `get::<u16>("port")` also accepts
+    /// `port: "8080"`. Only a value read as a whole is coerced, not the
+    /// fields of a struct or the items of a sequence.
+    pub fn with_lenient_scalars(mut self, lenient: bool) -> Self {
+        self.lenient_scalars = lenient;
+        self
+    }
+
+    /// Makes `get` and the other read methods, as well as `contains_key`,
+    /// match keys ignoring ASCII case, so `port` also finds `Port` or `PORT`.
+    /// If several keys differ only by case, the first one wins, reported as
+    /// `TraceEvent::AmbiguousKey` with the `trace` feature. Methods that
+    /// write or remove values follow the same match, so `set("port", 2)`
+    /// replaces an existing `Port` instead of adding a second key.
+    pub fn with_case_insensitive(mut self, enabled: bool) -> Self {
+        self.case_insensitive = enabled;
+        self
+    }
+
+    /// Whether the config file exists on disk, `false` if no file is set.
+    pub fn exists(&self) -> bool {
+        self.exists_result().unwrap_or(false)
+    }
+
+    /// Like `exists`, but fails with `FileNotSet` if no file is set.
+    pub fn exists_result(&self) -> Result<bool, Error> {
+        Ok(self.file()?.exists())
+    }
+
+    /// Uses `<config dir>/<app>/config.yaml` as the file, where the config
+    /// dir is the platform's usual location: `$XDG_CONFIG_HOME` or
+    /// `~/.config` on Linux and other Unixes, `~/Library/Application Support`
+    /// on macOS and `%APPDATA%` on Windows. The file is left unset if that
+    /// location can't be determined from the environment.
+    pub fn with_default_path(mut self, app: &str) -> Self {
+        if let Some(dir) = config_dir() {
+            let path = dir.join(app).join("config.yaml");
+            self.file = Some(path);
+        }
+        self
+    }
+
+    /// Creates the directories leading up to the configured file, so that a
+    /// first `write_to_file` can succeed on a fresh system.
+    pub fn ensure_parent_dirs(&self) -> Result<(), Error> {
+        match self.file()?.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => {
+                fs::create_dir_all(dir).map_err(|e| open_failed(dir, e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the file format explicitly instead of guessing it from the
+    /// file extension.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Reads `path`, the format guessed from its extension, into a new
+    /// config that saves back to it. Fails with `FileNotFound` if it doesn't
+    /// exist. Use `with_file` and `read_from_file` instead when options such
+    /// as `with_max_size` have to apply to the read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let mut config = Config::new().with_file(path);
+        config.read_from_file()?;
+        Ok(config)
+    }
+
+    /// Empty config that `write_to_file` saves to `path`. The file isn't
+    /// touched until then, so an existing one is overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> Config {
+        Config::new().with_file(path)
+    }
+
+    /// Parses a YAML document without touching the filesystem. Same as
+    /// `str::parse`, which works through the `FromStr` impl.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Result<Config, Error> {
+        let mut c = Config::new();
+        c.load(Format::Yaml, s)?;
+        Ok(c)
+    }
+
+    /// Wraps an already parsed tree, which has to be a mapping or null. The
+    /// config has no file set.
+    pub fn from_value(value: Value) -> Result<Config, Error> {
+        let mut config = Config::new();
+        config.root = into_root(value)?;
+        Ok(config)
+    }
+
+    /// Consumes the config and returns its tree. Unlike dropping it, this
+    /// never autosaves.
+    pub fn into_value(mut self) -> Value {
+        self.autosave = false;
+        std::mem::take(&mut self.root)
+    }
+
+    /// Serializes the config to a YAML document.
+    pub fn to_string(&self) -> Result<String, Error> {
+        Format::Yaml.serialize(&self.root, true)
+    }
+
+    /// Hash of the values, for telling cheaply whether a config changed,
+    /// e.g. between reloads. Key order doesn't matter. The hash is 64-bit
+    /// FNV-1a over the sorted YAML, so unlike std's hasher it stays the same
+    /// across runs and can be stored. It is no protection against deliberate
+    /// collisions.
+    pub fn checksum(&self) -> u64 {
+        let mut root = self.root.clone();
+        sort_keys(&mut root);
+        let yaml = Format::Yaml.serialize(&root, true).unwrap_or_default();
+        yaml.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// By default `write_to_file` writes a sibling `<file>.tmp` and renames it
+    /// over the target, so a crash mid-write never leaves a truncated config.
+    /// Pass `false` to write the file in place instead, e.g. when the
+    /// directory isn't writable or the file is a symlink that must be kept.
+    ///
+    /// The new file gets the permissions of the one it replaces before
+    /// anything is written to it. On Unix the owner and group are copied too
+    /// where the process may set them, which usually requires root. On
+    /// Windows only the read-only flag is copied; ACLs come from the
+    /// directory as for any new file.
+    pub fn with_atomic_write(mut self, atomic: bool) -> Self {
+        self.atomic_write = atomic;
+        self
+    }
+
+    /// By default `write_to_file` creates missing parent directories of the
+    /// file. Pass `false` to fail with `FileOpenFailed` instead.
+    pub fn with_create_dirs(mut self, create: bool) -> Self {
+        self.create_dirs = create;
+        self
+    }
+
+    /// When the format set with `with_format` doesn't match a known file
+    /// extension, e.g. JSON for `config.yaml`, the file is still read and
+    /// written in that format, reported as `TraceEvent::ExtensionMismatch`
+    /// with the `trace` feature. Pass `true` to fail with
+    /// `ExtensionFormatMismatch` instead.
+    pub fn with_strict_extension(mut self, strict: bool) -> Self {
+        self.strict_extension = strict;
+        self
+    }
+
+    /// JSON is pretty-printed by default so that hand-edited files diff
+    /// well. Pass `false` to write it on a single line instead. YAML and
+    /// TOML output is unaffected.
+    pub fn with_pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    /// Makes `write_to` and `write_to_file` sort mapping keys, including
+    /// nested ones, so the output doesn't depend on insertion order. The
+    /// in-memory config keeps its order.
+    pub fn with_sorted_keys(mut self, sorted: bool) -> Self {
+        self.sorted_keys = sorted;
+        self
+    }
+
+    /// By default `write_to` and `write_to_file` end the output with exactly
+    /// one newline, as POSIX text files do; JSON is otherwise written
+    /// without one. Blank lines ending a YAML `|+` string are part of its
+    /// value and are kept. Pass `false` to write the output as serialized.
+    pub fn with_trailing_newline(mut self, trailing_newline: bool) -> Self {
+        self.trailing_newline = trailing_newline;
+        self
+    }
+
+    /// Makes `read_from_file` fail with `FileTooLarge` instead of reading a
+    /// file bigger than `bytes`, e.g. when the file is user-supplied. There
+    /// is no limit by default.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Makes the config call `write_to_file` when it is dropped with unsaved
+    /// changes, see `is_dirty`. Errors can't be returned from `drop`, so a
+    /// failed write is only reported as `TraceEvent::SaveOnDropFailed` with
+    /// the `trace` feature and the changes are lost; call `save` explicitly
+    /// wherever that matters. Clones inherit the flag,
+    /// so each clone with changes writes the file as well.
+    pub fn with_autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    /// Writes `text` as a comment above the serialized config, one `# ` line
+    /// per line of `text`. Parsers skip it, so it is lost on the next read
+    /// and rewritten on every write. JSON has no comments, so the header is
+    /// left out of JSON files, reported as `TraceEvent::HeaderSkipped` with
+    /// the `trace` feature.
+    pub fn with_header_comment(mut self, text: &str) -> Self {
+        self.header_comment = Some(text.to_owned());
+        self
+    }
+
+    /// Reads the whole document from `reader`, replacing the current config.
+    /// Without an explicit format (or a file to guess it from) YAML is assumed.
+    pub fn read_from<R: Read>(&mut self, mut reader: R) -> Result<(), Error> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .map_err(|e| Error::Io(e.to_string()))?;
+        self.load(self.format(), &content)
+    }
+
+    /// Replaces the config with the document in `content`.
+    fn load(&mut self, format: Format, content: &str) -> Result<(), Error> {
+        self.root = parse_root(format, content)?;
+        self.docs = match format {
+            Format::Yaml => docs::extract(content, self.separator),
+            _ => HashMap::new(),
+        };
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Serializes the config into `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        let content = self.write_preview()?;
+        writer
+            .write_all(content.as_bytes())
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Io(e.to_string()))
+    }
+
+    /// Exactly what `write_to_file` would write, header comment and key
+    /// sorting included, without touching the file. Useful for showing a
+    /// diff against the current file before saving.
+    pub fn write_preview(&self) -> Result<String, Error> {
+        let content = if self.sorted_keys {
+            let mut root = self.root.clone();
+            sort_keys(&mut root);
+            self.format().serialize(&root, self.pretty)?
+        } else {
+            self.format().serialize(&self.root, self.pretty)?
+        };
+        let content = match self.format() {
+            Format::Yaml if self.anchors => anchors::share_repeated(&content),
+            _ => content,
+        };
+        let content = match self.format() {
+            Format::Yaml if !self.docs.is_empty() => {
+                docs::annotate(&content, &self.docs, self.separator)
+            }
+            _ => content,
+        };
+        let content = match &self.header_comment {
+            // The blank line keeps the header from being read back as the
+            // doc of the first key.
+            Some(header) => match self.format().comment(header) {
+                Some(comment) => comment + "\n" + &content,
+                None => {
+                    trace_event!(HeaderSkipped {
+                        format: self.format()
+                    });
+                    content
+                }
+            },
+            None => content,
+        };
+        let mut content = content;
+        if self.trailing_newline {
+            if self.format() != Format::Yaml {
+                content.truncate(content.trim_end_matches('\n').len());
+            }
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+        }
+        Ok(content)
+    }
+
+    pub fn read_from_file(&mut self) -> Result<(), Error> {
+        let path = self.file()?.to_owned();
+        self.check_extension(&path)?;
+        let f = File::open(&path).map_err(|e| read_failed(&path, e))?;
+        let _lock = self.lock_file(&path, false)?;
+        let meta = f.metadata().map_err(|e| open_failed(&path, e))?;
+        match self.max_size {
+            Some(limit) => {
+                let too_large = || Error::FileTooLarge {
+                    path: path.display().to_string(),
+                    limit,
+                };
+                if meta.len() > limit {
+                    return Err(too_large());
+                }
+                // The file may grow after the check, so never read past the limit.
+                let mut content = String::new();
+                f.take(limit + 1)
+                    .read_to_string(&mut content)
+                    .map_err(|e| open_failed(&path, e))?;
+                if content.len() as u64 > limit {
+                    return Err(too_large());
+                }
+                self.load(self.format(), &content)?;
+            }
+            None => self.read_from(f).map_err(|e| in_file(e, &path))?,
+        }
+        self.file_stamp = file_stamp(&meta);
+        trace_event!(Read { path: &path });
+        Ok(())
+    }
+
+    /// Whether the file changed since it was last read or written, judging
+    /// by its modification time and size, so a poller can call `reload` only
+    /// when needed. It is always stale if it was never read. Fails with
+    /// `FileNotFound` if the file is gone.
+    ///
+    /// Modification times are coarse on some filesystems, e.g. 2 seconds on
+    /// FAT, so a change made right after the read that keeps the size is
+    /// missed until the next one. A file replaced by an older copy counts as
+    /// stale.
+    pub fn is_file_stale(&self) -> Result<bool, Error> {
+        let path = self.file()?;
+        let meta = fs::metadata(path).map_err(|e| read_failed(path, e))?;
+        meta.modified().map_err(|e| open_failed(path, e))?;
+        Ok(self.file_stamp.is_none() || self.file_stamp != file_stamp(&meta))
+    }
+
+    /// Reads the file if it exists. Otherwise stores `defaults`, which has
+    /// to serialize to a mapping, as the whole config and writes it to the
+    /// file, so a first run leaves a config behind for the user to edit.
+    pub fn load_or_create<T: Serialize>(&mut self, defaults: &T) -> Result<LoadOutcome, Error> {
+        match self.read_from_file() {
+            Ok(()) => return Ok(LoadOutcome::Loaded),
+            Err(Error::FileNotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.set_root(defaults)?;
+        self.write_to_file()?;
+        Ok(LoadOutcome::Created)
+    }
+
+    /// Sets the candidate files for `read_first_existing`, most preferred
+    /// first.
+    pub fn with_search_paths<P: AsRef<Path>>(mut self, paths: &[P]) -> Self {
+        self.search_paths = paths.iter().map(|p| p.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Reads the first of the search paths that exists and makes it the
+    /// config's file, so `write_to_file` saves back to it. Fails with
+    /// `NoConfigFound` listing every path tried if none exists.
+    pub fn read_first_existing(&mut self) -> Result<(), Error> {
+        let found = match self.search_paths.iter().find(|p| p.exists()) {
+            Some(path) => path.clone(),
+            None => {
+                let tried = self.search_paths.iter();
+                let tried = tried.map(|p| p.display().to_string()).collect();
+                return Err(Error::NoConfigFound(tried));
+            }
+        };
+        self.file = Some(found);
+        self.read_from_file()
+    }
+
+    /// Re-reads the configured file, picking up edits made since it was
+    /// loaded. If the file can't be read or parsed the error is returned and
+    /// the previously loaded values stay in place untouched.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        trace_event!(Reload { path: self.file()? });
+        self.read_from_file()
+    }
+
+    pub fn write_to_file(&mut self) -> Result<(), Error> {
+        self.persist()?;
+        self.dirty = false;
+        self.file_stamp = fs::metadata(self.file()?).ok().and_then(|m| file_stamp(&m));
+        trace_event!(Write { path: self.file()? });
+        Ok(())
+    }
+
+    /// Same as `write_to_file`.
+    pub fn save(&mut self) -> Result<(), Error> {
+        self.write_to_file()
+    }
+
+    /// Writes the file only if `is_dirty`, so an unchanged config doesn't
+    /// rewrite the file and bump its modification time.
+    pub fn save_if_dirty(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            self.write_to_file()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the config was changed since it was last read or written.
+    /// Every mutating call counts, even if it stores the value that was
+    /// already there.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn persist(&self) -> Result<(), Error> {
+        self.writable()?;
+        let path = self.file()?;
+        self.check_extension(path)?;
+        if self.create_dirs {
+            self.ensure_parent_dirs()?;
+        }
+        let _lock = self.lock_file(path, true)?;
+        if !self.atomic_write {
+            return self.write_file(path, None);
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let original = fs::metadata(path).ok();
+        let result = self
+            .write_file(&tmp, original.as_ref())
+            .and_then(|_| fs::rename(&tmp, path).map_err(|e| open_failed(path, e)));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    /// Takes the lock set up with `with_file_lock` for the file at `path`,
+    /// exclusive for writing and shared for reading. It is held until the
+    /// returned file is dropped.
+    fn lock_file(&self, path: &Path, exclusive: bool) -> Result<Option<File>, Error> {
+        if !self.file_lock {
+            return Ok(None);
+        }
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(|e| open_failed(&lock_path, e))?;
+        let timeout = match self.lock_timeout {
+            Some(timeout) => timeout,
+            None => {
+                let locked = if exclusive { f.lock() } else { f.lock_shared() };
+                locked.map_err(|e| open_failed(&lock_path, e))?;
+                return Ok(Some(f));
+            }
+        };
+        let start = Instant::now();
+        loop {
+            let locked = if exclusive {
+                f.try_lock()
+            } else {
+                f.try_lock_shared()
+            };
+            match locked {
+                Ok(()) => return Ok(Some(f)),
+                Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::LockTimeout(path.display().to_string()))
+                }
+                Err(TryLockError::Error(e)) => return Err(open_failed(&lock_path, e)),
+            }
+        }
+    }
+
+    /// Writes the config to `path`, first giving it the permissions and, if
+    /// allowed, the owner of `like`, so its contents are never readable by
+    /// more users than the file it replaces.
+    fn write_file(&self, path: &Path, like: Option<&fs::Metadata>) -> Result<(), Error> {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| open_failed(path, e))?;
+        if let Some(meta) = like {
+            f.set_permissions(meta.permissions())
+                .map_err(|e| open_failed(path, e))?;
+            // Only root can give a file away, so keep our own otherwise.
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::{fchown, MetadataExt};
+                let _ = fchown(&f, Some(meta.uid()), Some(meta.gid()));
+            }
+        }
+        self.write_to(&mut f).map_err(|e| in_file(e, path))?;
+        f.sync_all().map_err(|e| open_failed(path, e))
+    }
+
+    /// Stores `value` at `name`, replacing any existing value and creating
+    /// intermediate mappings as needed. Same as `set`.
+    ///
+    /// Returns the value previously stored at `name`. It is `None` both when
+    /// there was none and when the old value doesn't deserialize into `T`;
+    /// use `contains_key` beforehand if the two need telling apart. Handing
+    /// back the old value is also why `T` has to be `DeserializeOwned`.
+    pub fn add<T>(&mut self, name: &str, value: T) -> Result<Option<T>, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let value = to_value(name, value)?;
+        let old = self.insert_value(name, value)?;
+        trace_event!(Add { key: name });
+        Ok(old.and_then(|v| serde_yaml::from_value(v).ok()))
+    }
+
+    /// Stores a raw value at `name`, returning the one it replaced.
+    fn insert_value(&mut self, name: &str, value: Value) -> Result<Option<Value>, Error> {
+        let name = self.stored_path(name);
+        let sep = self.separator;
+        path::insert(self.root_mut()?, &name, value, sep)
+    }
+
+    /// Stores `value` at `name`, overwriting and returning any existing
+    /// value like `add` does.
+    pub fn set<T>(&mut self, name: &str, value: T) -> Result<Option<T>, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.add(name, value)
+    }
+
+    /// Like `set`, but fails with `KeyExists` instead of overwriting.
+    pub fn try_add<T>(&mut self, name: &str, value: T) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        if path::lookup(&self.root, name, self.separator)?.is_some() {
+            return Err(Error::KeyExists(name.to_owned()));
+        }
+        let value = to_value(name, value)?;
+        self.insert_value(name, value).map(|_| ())
+    }
+
+    /// Stores every `(name, value)` pair like `set` does. Either all of them
+    /// are stored or, if one fails to serialize or can't be inserted, none
+    /// are and the error names the failing key.
+    pub fn set_many<T, I>(&mut self, entries: I) -> Result<(), Error>
+    where
+        T: Serialize,
+        I: IntoIterator<Item = (String, T)>,
+    {
+        let values = entries
+            .into_iter()
+            .map(|(name, value)| Ok((to_value(&name, value)?, name)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut root = self.root.clone();
+        for (value, name) in values {
+            let name = self.stored_path(&name);
+            path::insert(&mut root, &name, value, self.separator)?;
+        }
+        *self.root_mut()? = root;
+        Ok(())
+    }
+
+    /// Builder form of `set`, for literal values in code:
+    ///
+    /// ```
+    /// let config = tuneup::Config::new()
+    ///     .with_value("port", 8080)
+    ///     .with_value("host", "0.0.0.0");
+    /// assert_eq!(config.get::<u16>("port"), Ok(8080));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` can't be serialized or `name` goes through a value
+    /// that isn't a mapping. Both are bugs in the calling code rather than
+    /// runtime conditions. Use `try_with_value` for values that come from
+    /// elsewhere.
+    pub fn with_value<T: Serialize>(self, name: &str, value: T) -> Self {
+        match self.try_with_value(name, value) {
+            Ok(config) => config,
+            Err(e) => panic!("can't set `{}`: {}", name, e),
+        }
+    }
+
+    /// Like `with_value`, but returns the error instead of panicking.
+    pub fn try_with_value<T: Serialize>(mut self, name: &str, value: T) -> Result<Self, Error> {
+        let value = to_value(name, value)?;
+        self.insert_value(name, value)?;
+        Ok(self)
+    }
+
+    pub fn get<T>(&self, name: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        if let Some(s) = self.lookup(name)? {
+            self.deserialize_value(name, s)
+        } else {
+            Err(Error::KeyNotFound(name.to_owned()))
+        }
+    }
+
+    /// Same as `get`, but a missing key fails with `RequiredKeyMissing`,
+    /// whose message is meant to be shown to the user as is, e.g. at
+    /// startup: "required config key `db.url` is missing".
+    pub fn required<T>(&self, name: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.get(name).map_err(|e| match e {
+            Error::KeyNotFound(_) => Error::RequiredKeyMissing(name.to_owned()),
+            e => e,
+        })
+    }
+
+    /// Stores `bytes` at `name` as a base64 string (standard alphabet, with
+    /// padding), the way binary data such as keys or certificates is kept in
+    /// text formats. Read it back with `get_bytes`.
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        self.insert_value(name, Value::String(base64::encode(bytes)))
+            .map(|_| ())
+    }
+
+    /// Decodes the base64 string stored at `name` by `add_bytes`. Whitespace
+    /// is ignored, so long values may be wrapped over several lines. Fails
+    /// with `DeserializationFailed` if the value isn't valid base64.
+    pub fn get_bytes(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let encoded: String = self.get(name)?;
+        base64::decode(&encoded).map_err(|message| deserialization_failed(name, message))
+    }
+
+    /// Tag and inner value of a tagged value such as `password: !secret x`,
+    /// or `None` if `name` isn't set or its value has no tag. Tagged values
+    /// are kept as they are when the config is read and written, and `get`
+    /// deserializes the inner value, so `get::<String>("password")` returns
+    /// `x`. Compare the tag with a string, e.g. `tag == "secret"`; the
+    /// leading `!` is optional.
+    pub fn get_tagged(&self, name: &str) -> Option<(&Tag, &Value)> {
+        match self.lookup(name) {
+            Ok(Some(Value::Tagged(t))) => Some((&t.tag, &t.value)),
+            _ => None,
+        }
+    }
+
+    /// String value at `name`. Like the other scalar getters this is `get`
+    /// with the type spelled out, but a mismatch fails with a message such
+    /// as "expected string, found integer".
+    pub fn get_str(&self, name: &str) -> Result<String, Error> {
+        self.get_scalar(name, "string")
+    }
+
+    pub fn get_i64(&self, name: &str) -> Result<i64, Error> {
+        self.get_scalar(name, "integer")
+    }
+
+    pub fn get_u64(&self, name: &str) -> Result<u64, Error> {
+        self.get_scalar(name, "non-negative integer")
+    }
+
+    /// Integers are accepted and converted.
+    pub fn get_f64(&self, name: &str) -> Result<f64, Error> {
+        self.get_scalar(name, "number")
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<bool, Error> {
+        self.get_scalar(name, "boolean")
+    }
+
+    fn get_scalar<T: DeserializeOwned>(&self, name: &str, expected: &str) -> Result<T, Error> {
+        let v = match self.lookup(name)? {
+            Some(v) => v,
+            None => return Err(Error::KeyNotFound(name.to_owned())),
+        };
+        self.deserialize_value(name, v).map_err(|e| match e {
+            Error::DeserializationFailed { .. } => {
+                let message = format!("expected {}, found {}", expected, kind(v));
+                deserialization_failed(name, message)
+            }
+            e => e,
+        })
+    }
+
+    /// Deserializes a value taken from the tree.
+    fn deserialize_value<T: DeserializeOwned>(&self, key: &str, v: &Value) -> Result<T, Error> {
+        let v = v.to_owned();
+        let coerced = match &v {
+            Value::String(s) if self.lenient_scalars => match parse_scalar(s) {
+                scalar @ Value::Number(_) | scalar @ Value::Bool(_) => Some(scalar),
+                _ => None,
+            },
+            _ => None,
+        };
+        serde_yaml::from_value(v)
+            .or_else(|e| match coerced {
+                Some(scalar) => serde_yaml::from_value(scalar).map_err(|_| e),
+                None => Err(e),
+            })
+            .map_err(|e| deserialization_failed(key, e.to_string()))
+    }
+
+    /// Deserializes every child of the mapping at `prefix` into `T`, keyed by
+    /// child name. Fails with `NotAMapping` if `prefix` holds anything else.
+    /// Children with non-string keys are skipped, as in `keys`.
+    pub fn get_all<T>(&self, prefix: &str) -> Result<HashMap<String, T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self.lookup(prefix)? {
+            Some(Value::Mapping(m)) => m
+                .iter()
+                .filter_map(|(k, v)| k.as_str().map(|k| (k, v)))
+                .map(|(k, v)| {
+                    let key = path::join(&[prefix, k], self.separator);
+                    Ok((k.to_owned(), self.deserialize_value(&key, v)?))
+                })
+                .collect(),
+            Some(_) => Err(Error::NotAMapping(prefix.to_owned())),
+            None => Err(Error::KeyNotFound(prefix.to_owned())),
+        }
+    }
+
+    /// Copy of the mapping at `prefix` as a config of its own, so a module
+    /// can be handed just its part of the tree. The copy keeps the path
+    /// separator, key matching and scalar coercion, but has no file:
+    /// changes to it are never saved on their own. Store them back under
+    /// `prefix` with `splice_subsection`.
+    pub fn subsection(&self, prefix: &str) -> Result<Config, Error> {
+        let root = match self.lookup(prefix)? {
+            Some(v @ Value::Mapping(_)) => v.clone(),
+            Some(_) => return Err(Error::NotAMapping(prefix.to_owned())),
+            None => return Err(Error::KeyNotFound(prefix.to_owned())),
+        };
+        let mut sub = Config::new()
+            .with_path_separator(self.separator)
+            .with_case_insensitive(self.case_insensitive)
+            .with_lenient_scalars(self.lenient_scalars);
+        sub.root = root;
+        Ok(sub)
+    }
+
+    /// Replaces the value at `prefix` with the whole of `sub`, typically a
+    /// config returned by `subsection` and then changed.
+    pub fn splice_subsection(&mut self, prefix: &str, sub: &Config) -> Result<(), Error> {
+        self.insert_value(prefix, sub.root.clone()).map(|_| ())
+    }
+
+    /// Deserializes the section at `prefix`, or gives `T::default()` if the
+    /// section is missing or null. Fields missing from a present section are
+    /// filled in by serde, so mark `T` or its fields `#[serde(default)]` to
+    /// have them keep their default.
+    pub fn get_section<T>(&self, prefix: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned + Default,
+    {
+        match self.lookup(prefix)? {
+            None | Some(Value::Null) => Ok(T::default()),
+            Some(section) => self.deserialize_value(prefix, section),
+        }
+    }
+
+    /// Deserializes the items of the sequence at `name` one at a time, so a
+    /// bad item is reported on its own, with its index in the key, instead
+    /// of failing the whole list. Fails up front with `NotASequence` if
+    /// `name` holds anything else.
+    pub fn iter_seq<T>(
+        &self,
+        name: &str,
+    ) -> Result<impl Iterator<Item = Result<T, Error>> + '_, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let seq = match self.lookup(name)? {
+            Some(Value::Sequence(seq)) => seq,
+            Some(_) => return Err(Error::NotASequence(name.to_owned())),
+            None => return Err(Error::KeyNotFound(name.to_owned())),
+        };
+        let name = name.to_owned();
+        Ok(seq.iter().enumerate().map(move |(i, item)| {
+            let key = path::join(&[&name, &i.to_string()], self.separator);
+            self.deserialize_value(&key, item)
+        }))
+    }
+
+    /// Like `get`, but returns `Ok(None)` if `name` is not set, so only a
+    /// value that fails to deserialize is an error.
+    pub fn get_opt<T>(&self, name: &str) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self.get(name) {
+            Ok(v) => Ok(Some(v)),
+            Err(Error::KeyNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `get`, but returns `default` if `name` is not set.
+    /// A value that is present but fails to deserialize is still an error.
+    pub fn get_or<T>(&self, name: &str, default: T) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_or_else(name, || default)
+    }
+
+    /// Like `get_or`, but computes the default only when it's needed.
+    pub fn get_or_else<T, F>(&self, name: &str, default: F) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        match self.get(name) {
+            Err(Error::KeyNotFound(_)) => Ok(default()),
+            r => r,
+        }
+    }
+
+    /// Returns the value at `name`, inserting `default` first if it's not set.
+    /// A key explicitly set to null counts as present and is left alone, see
+    /// `get_or_insert_replacing_null`. Like `add`, this only changes the
+    /// in-memory config; call `write_to_file` to persist the default.
+    pub fn get_or_insert<T>(&mut self, name: &str, default: T) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.get_or_insert_impl(name, default, false)
+    }
+
+    /// Like `get_or_insert`, but also overwrites a null value with `default`.
+    pub fn get_or_insert_replacing_null<T>(&mut self, name: &str, default: T) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.get_or_insert_impl(name, default, true)
+    }
+
+    fn get_or_insert_impl<T>(
+        &mut self,
+        name: &str,
+        default: T,
+        replace_null: bool,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        match self.lookup(name)? {
+            Some(Value::Null) if replace_null => {}
+            Some(_) => return self.get(name),
+            None => {}
+        }
+        let value = to_value(name, &default)?;
+        self.insert_value(name, value)?;
+        Ok(default)
+    }
+
+    /// Deserializes the value at `name`, lets `f` change it and stores the
+    /// result back under the key it was read from. Fails with `KeyNotFound` if `name` is not set, see
+    /// `update_or_default`.
+    pub fn update<T, F>(&mut self, name: &str, f: F) -> Result<(), Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(&mut T),
+    {
+        let mut value = self.get(name)?;
+        f(&mut value);
+        let value = to_value(name, value)?;
+        self.insert_value(name, value).map(|_| ())
+    }
+
+    /// Like `update`, but starts from `T::default()` if `name` is not set.
+    pub fn update_or_default<T, F>(&mut self, name: &str, f: F) -> Result<(), Error>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnOnce(&mut T),
+    {
+        let mut value = self.get_opt(name)?.unwrap_or_default();
+        f(&mut value);
+        let value = to_value(name, value)?;
+        self.insert_value(name, value).map(|_| ())
+    }
+
+    /// Overrides values with environment variables named `<prefix>_<KEY>`.
+    /// The key is lowercased and `__` separates nested segments, so with
+    /// prefix `APP` the variable `APP_DB__PORT=5432` sets `db.port` to the
+    /// integer 5432. Values are parsed as YAML scalars. An empty prefix
+    /// takes every variable, so `DB__PORT` sets `db.port`.
+    ///
+    /// Overrides replace whatever is currently loaded, so call this after
+    /// `read_from_file` to let the environment take precedence over the file.
+    pub fn apply_env_overrides(&mut self, prefix: &str) -> Result<(), Error> {
+        self.apply_overrides(prefix, std::env::vars())
+    }
+
+    /// Applies the `KEY=VALUE` lines of a dotenv-style file the same way
+    /// `apply_env_overrides` applies environment variables. Blank lines and
+    /// lines starting with `#` are skipped; there are no quoting rules, the
+    /// value is everything after the first `=` with surrounding whitespace
+    /// trimmed.
+    pub fn merge_env_file<P: AsRef<Path>>(&mut self, path: P, prefix: &str) -> Result<(), Error> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| read_failed(path, e))?;
+        let mut vars = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, value)) => vars.push((name.trim().to_owned(), value.trim().to_owned())),
+                None => {
+                    let message =
+                        format!("`{}` line {}: expected KEY=VALUE", path.display(), i + 1);
+                    return Err(deserialization_failed("", message));
+                }
+            }
+        }
+        self.apply_overrides(prefix, vars)
+    }
+
+    /// Applies `key=value` overrides given on the command line, e.g. the
+    /// values of repeated `--set` flags. Keys are paths like `db.port` and
+    /// values are parsed as YAML scalars, as in `apply_env_overrides`.
+    /// Nothing is applied if any argument lacks a `=` or has an empty key.
+    pub fn apply_cli_overrides<S: AsRef<str>>(&mut self, args: &[S]) -> Result<(), Error> {
+        let overrides = args
+            .iter()
+            .map(|arg| match arg.as_ref().split_once('=') {
+                Some((key, value)) if !key.is_empty() => Ok((key, parse_scalar(value))),
+                _ => Err(Error::MalformedOverride(arg.as_ref().to_owned())),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        for (key, value) in overrides {
+            self.insert_value(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn apply_overrides<I>(&mut self, prefix: &str, vars: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let prefix = prefix.trim_end_matches('_');
+        for (name, value) in vars {
+            let key = if prefix.is_empty() {
+                Some(name.as_str())
+            } else {
+                name.strip_prefix(prefix).and_then(|k| k.strip_prefix('_'))
+            };
+            let key = match key {
+                Some(k) if !k.is_empty() => k,
+                _ => continue,
+            };
+            let path = key
+                .to_lowercase()
+                .replace("__", &self.separator.to_string());
+            self.insert_value(&path, parse_scalar(&value))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces `${VAR}` and `$VAR` in all string values with the value of
+    /// the environment variable, and `$$` with a literal `$`. References to
+    /// unset variables are left as they are, see `expand_env_strict`.
+    pub fn expand_env(&mut self) -> Result<(), Error> {
+        interpolate::expand_value(self.root_mut()?, false, &|v| std::env::var(v).ok())
+    }
+
+    /// Like `expand_env`, but fails with `UndefinedEnvVar` if a referenced
+    /// variable isn't set. The config is left partially expanded then.
+    pub fn expand_env_strict(&mut self) -> Result<(), Error> {
+        interpolate::expand_value(self.root_mut()?, true, &|v| std::env::var(v).ok())
+    }
+
+    /// Deep-merges `other` into this config, see `merge_with`.
+    /// Sequences from `other` replace existing ones.
+    pub fn merge(&mut self, other: &Config) {
+        self.merge_with(other, MergeStrategy::Overwrite)
+    }
+
+    /// Reads `path` and deep-merges it into this config like `merge` does,
+    /// e.g. to apply a local override file on top of a base file. The format
+    /// is guessed from `path`, and the config's own file stays unchanged.
+    pub fn merge_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let mut layer = Config::new().with_file(path);
+        layer.max_size = self.max_size;
+        layer.read_from_file()?;
+        merge::merge_values(self.root_mut()?, &layer.root, MergeStrategy::Overwrite);
+        Ok(())
+    }
+
+    /// Reads `files` in order and deep-merges each into this config like
+    /// `merge_file`, later files winning, e.g. a system, a user and a
+    /// project file. The last of `files` becomes the config's own file, so
+    /// `write_to_file` saves to the most specific one. With `skip_missing`
+    /// files that don't exist are left out, otherwise they fail with
+    /// `FileNotFound`.
+    ///
+    /// The config only changes if every file was read. A file that doesn't
+    /// parse or whose root isn't a mapping fails with
+    /// `DeserializationFailed` naming it in the message.
+    pub fn merge_all<P: AsRef<Path>>(
+        &mut self,
+        files: &[P],
+        skip_missing: bool,
+    ) -> Result<(), Error> {
+        let mut root = self.root.clone();
+        for path in files {
+            let path = path.as_ref();
+            let mut layer = Config::new().with_file(path);
+            layer.max_size = self.max_size;
+            match layer.read_from_file() {
+                Ok(()) => merge::merge_values(&mut root, &layer.root, MergeStrategy::Overwrite),
+                Err(Error::FileNotFound(_)) if skip_missing => {}
+                Err(Error::DeserializationFailed { key, message }) => {
+                    let message = format!("in `{}`: {}", path.display(), message);
+                    return Err(Error::DeserializationFailed { key, message });
+                }
+                Err(e @ Error::RootNotMapping) => {
+                    let message = format!("in `{}`: {}", path.display(), e);
+                    return Err(deserialization_failed("", message));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        *self.root_mut()? = root;
+        if let Some(last) = files.last() {
+            self.file = Some(last.as_ref().to_owned());
+        }
+        Ok(())
+    }
+
+    /// Parses the YAML document `s` and deep-merges it into this config like
+    /// `merge` does, e.g. for a tweak in a test. Fails with `RootNotMapping`
+    /// if `s` isn't a mapping, leaving the config unchanged.
+    pub fn merge_str(&mut self, s: &str) -> Result<(), Error> {
+        let layer = parse_root(Format::Yaml, s)?;
+        merge::merge_values(self.root_mut()?, &layer, MergeStrategy::Overwrite);
+        Ok(())
+    }
+
+    /// Deep-merges `other` into this config: mappings are merged key by key
+    /// and every other value from `other` wins, with `strategy` deciding
+    /// whether sequences are replaced or appended to.
+    pub fn merge_with(&mut self, other: &Config, strategy: MergeStrategy) {
+        // Can't fail, so a read-only config is silently left alone.
+        if self.readonly {
+            return;
+        }
+        self.dirty = true;
+        merge::merge_values(&mut self.root, &other.root, strategy)
+    }
+
+    /// Deep-merges a raw mapping into this config, see `merge_with`.
+    pub fn merge_value(&mut self, other: &Value, strategy: MergeStrategy) -> Result<(), Error> {
+        if !other.is_mapping() {
+            return Err(Error::RootNotMapping);
+        }
+        merge::merge_values(self.root_mut()?, other, strategy);
+        Ok(())
+    }
+
+    /// Fills in keys missing from the config with the ones from `defaults`,
+    /// typically a struct holding all default values. Values already set win,
+    /// including whole sequences. `defaults` has to serialize to a mapping.
+    pub fn apply_defaults<T: Serialize>(&mut self, defaults: &T) -> Result<(), Error> {
+        let mut base = serde_yaml::to_value(defaults)
+            .map_err(|e| Error::SerializationFailed(e.to_string()))?;
+        if !base.is_mapping() {
+            return Err(Error::RootNotMapping);
+        }
+        merge::merge_values(&mut base, &self.root, MergeStrategy::Overwrite);
+        *self.root_mut()? = base;
+        Ok(())
+    }
+
+    /// Deserializes the whole config into `T`, e.g. an application's
+    /// top-level settings struct.
+    pub fn deserialize_root<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        self.deserialize_value("", &self.root)
+    }
+
+    /// Replaces the whole config with `value`, which has to serialize to a
+    /// mapping.
+    pub fn set_root<T: Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match serde_yaml::to_value(value) {
+            Ok(v @ Value::Mapping(_)) => {
+                *self.root_mut()? = v;
+                Ok(())
+            }
+            Ok(_) => Err(Error::RootNotMapping),
+            Err(e) => Err(Error::SerializationFailed(e.to_string())),
+        }
+    }
+
+    /// Raw value of the top-level key `name`, without deserializing it. Dots
+    /// in `name` are part of the key, see `raw_path` for nested values.
+    pub fn raw(&self, name: &str) -> Option<&Value> {
+        self.root.as_mapping()?.get(name)
+    }
+
+    /// Raw value at the dotted `path`, or `None` if nothing is stored there.
+    pub fn raw_path(&self, path: &str) -> Option<&Value> {
+        self.lookup(path).ok().flatten()
+    }
+
+    /// Checks whether `name` is set without deserializing its value.
+    pub fn contains_key(&self, name: &str) -> bool {
+        matches!(self.lookup(name), Ok(Some(_)))
+    }
+
+    /// Top-level keys in insertion order. Keys that are not YAML strings
+    /// (numbers, booleans, ...) are skipped.
+    pub fn keys(&self) -> Vec<String> {
+        match self.root.as_mapping() {
+            Some(m) => m
+                .keys()
+                .filter_map(|k| k.as_str().map(str::to_owned))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Top-level entries in insertion order, skipping non-string keys like
+    /// `keys` does.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.root
+            .as_mapping()
+            .into_iter()
+            .flat_map(|m| m.iter())
+            .filter_map(|(k, v)| k.as_str().map(|k| (k, v)))
+    }
+
+    /// Number of top-level keys. A root that isn't a mapping counts as empty.
+    pub fn len(&self) -> usize {
+        self.root.as_mapping().map_or(0, Mapping::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every key, keeping the file path and other settings.
+    pub fn clear(&mut self) {
+        // Can't fail, so a read-only config is silently left alone.
+        if self.readonly {
+            return;
+        }
+        self.dirty = true;
+        self.root = Value::Mapping(Mapping::new());
+        self.docs.clear();
+    }
+
+    /// Removes the value at `name`, returning whether it was present.
+    /// Only the in-memory config is changed, call `write_to_file` to persist.
+    pub fn remove(&mut self, name: &str) -> Result<bool, Error> {
+        self.writable()?;
+        let matched = self.stored_path(name);
+        let removed = path::remove(&mut self.root, &matched, self.separator)?.is_some();
+        self.dirty |= removed;
+        if removed {
+            docs::remove(&mut self.docs, &matched, self.separator);
+            trace_event!(Remove { key: name });
+        }
+        Ok(removed)
+    }
+
+    /// Removes keys set to null at any depth, including in mappings inside
+    /// sequences, and returns how many keys were removed. Null sequence
+    /// elements stay, since removing them would shift the ones after.
+    ///
+    /// With `keep_top_level` nulls directly at the root are kept, for
+    /// callers that use a null key to mean something. With `drop_empty` a
+    /// mapping left empty by the pruning is removed too; one that was empty
+    /// to begin with is kept.
+    pub fn prune_nulls(&mut self, keep_top_level: bool, drop_empty: bool) -> Result<usize, Error> {
+        self.writable()?;
+        let removed = match &mut self.root {
+            Value::Mapping(m) => prune(m, keep_top_level, drop_empty),
+            _ => 0,
+        };
+        self.dirty |= removed > 0;
+        Ok(removed)
+    }
+
+    /// Like `get`, but also removes the value, e.g. a one-time token that
+    /// mustn't be used twice. A value that fails to deserialize stays in
+    /// place.
+    pub fn take<T: DeserializeOwned>(&mut self, name: &str) -> Result<T, Error> {
+        self.writable()?;
+        let value = self.get(name)?;
+        let matched = self.stored_path(name);
+        path::remove(&mut self.root, &matched, self.separator)?;
+        docs::remove(&mut self.docs, &matched, self.separator);
+        self.dirty = true;
+        Ok(value)
+    }
+
+    /// Moves the value at `old` to `new`. Fails with `KeyNotFound` if `old`
+    /// isn't set and with `KeyExists` if `new` already is. A key renamed
+    /// within the same mapping keeps its position, so the file's layout stays
+    /// stable.
+    pub fn rename_key(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        let old = self.stored_path(old).into_owned();
+        let sep = self.separator;
+        path::rename(self.root_mut()?, &old, new, sep)?;
+        docs::rename(&mut self.docs, &old, new, sep);
+        Ok(())
+    }
+
+    /// Appends `value` to the sequence at `name`, creating the sequence if
+    /// the key isn't set yet.
+    pub fn push<T>(&mut self, name: &str, value: T) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        let value = to_value(name, value)?;
+        let name = self.stored_path(name);
+        let sep = self.separator;
+        path::push(self.root_mut()?, &name, value, sep)
+    }
+
+    /// `name` spelled as stored for the write methods, honoring
+    /// `with_case_insensitive`.
+    fn stored_path<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.case_insensitive {
+            Cow::Owned(path::resolve_ignore_case(&self.root, name, self.separator))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    /// Value at `name` for the read methods, honoring `with_case_insensitive`.
+    fn lookup(&self, name: &str) -> Result<Option<&Value>, Error> {
+        if self.case_insensitive {
+            path::lookup_ignore_case(&self.root, name, self.separator)
+        } else {
+            path::lookup(&self.root, name, self.separator)
+        }
+    }
+
+    /// Mutable access to the tree, marking the config as changed. Fails
+    /// with `ReadOnly` if the config is read-only.
+    fn root_mut(&mut self) -> Result<&mut Value, Error> {
+        self.writable()?;
+        self.dirty = true;
+        Ok(&mut self.root)
+    }
+
+    fn writable(&self) -> Result<(), Error> {
+        if self.readonly {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn file(&self) -> Result<&Path, Error> {
+        self.file.as_deref().ok_or(Error::FileNotSet)
+    }
+
+    fn check_extension(&self, path: &Path) -> Result<(), Error> {
+        let format = match (self.format, Format::from_extension(path)) {
+            (Some(f), Some(implied)) if f != implied => f,
+            _ => return Ok(()),
+        };
+        if self.strict_extension {
+            return Err(Error::ExtensionFormatMismatch {
+                path: path.display().to_string(),
+                format,
+            });
+        }
+        trace_event!(ExtensionMismatch { path, format });
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        match (self.format, self.file.as_deref()) {
+            (Some(f), _) => f,
+            (None, Some(path)) => Format::from_path(path),
+            (None, None) => Format::Yaml,
+        }
+    }
+}
+
+/// Parses `s` as a YAML scalar, falling back to a plain string for empty
+/// input or anything that would parse as a collection.
+fn parse_scalar(s: &str) -> Value {
+    match serde_yaml::from_str::<Value>(s) {
+        Ok(Value::Sequence(_)) | Ok(Value::Mapping(_)) | Err(_) => Value::String(s.to_owned()),
+        Ok(_) if s.trim().is_empty() => Value::String(s.to_owned()),
+        Ok(v) => v,
+    }
+}
+
+/// Serializes a value about to be stored at `name`, naming the key and the
+/// type if that fails.
+fn to_value<T: Serialize>(name: &str, value: T) -> Result<Value, Error> {
+    serde_yaml::to_value(value).map_err(|e| {
+        let type_name = std::any::type_name::<T>();
+        Error::SerializationFailed(format!("`{}` ({}): {}", name, type_name, e))
+    })
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let from_env = |var| {
+        std::env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        from_env("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        from_env("HOME").map(|h| h.join("Library").join("Application Support"))
+    } else {
+        from_env("XDG_CONFIG_HOME").or_else(|| from_env("HOME").map(|h| h.join(".config")))
+    }
+}
+
+/// What `v` is, for error messages.
+fn kind(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(n) if n.as_i64().is_none() => "large integer",
+        Value::Number(n) if n.as_i64().is_some_and(|i| i < 0) => "negative integer",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Sequence(_) => "sequence",
+        Value::Mapping(_) => "mapping",
+        Value::Tagged(t) => kind(&t.value),
+    }
+}
+
+fn deserialization_failed(key: &str, message: String) -> Error {
+    Error::DeserializationFailed {
+        key: key.to_owned(),
+        message,
+    }
+}
+
+fn file_stamp(meta: &fs::Metadata) -> Option<(SystemTime, u64)> {
+    meta.modified().ok().map(|t| (t, meta.len()))
+}
+
+/// Like `open_failed`, but tells a missing file apart.
+fn read_failed(path: &Path, e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::NotFound => Error::FileNotFound(path.display().to_string()),
+        _ => open_failed(path, e),
+    }
+}
+
+fn open_failed(path: &Path, e: std::io::Error) -> Error {
+    Error::FileOpenFailed {
+        path: path.display().to_string(),
+        source: e.to_string(),
+    }
+}
+
+/// Attaches `path` to an I/O error coming from a file's reader or writer.
+fn in_file(e: Error, path: &Path) -> Error {
+    match e {
+        Error::Io(source) => Error::FileOpenFailed {
+            path: path.display().to_string(),
+            source,
+        },
+        e => e,
+    }
+}
+
+/// Removes the null entries of `m` unless `keep_nulls`, and those below it,
+/// returning how many keys were removed.
+fn prune(m: &mut Mapping, keep_nulls: bool, drop_empty: bool) -> usize {
+    let mut removed = 0;
+    m.retain(|_, v| {
+        let keep = match v {
+            Value::Null => keep_nulls,
+            v => {
+                let (n, emptied) = prune_below(v, drop_empty);
+                removed += n;
+                !(drop_empty && emptied)
+            }
+        };
+        if !keep {
+            removed += 1;
+        }
+        keep
+    });
+    removed
+}
+
+/// Prunes the mappings within `v`, returning how many keys were removed
+/// and whether `v` is a mapping that ended up empty.
+fn prune_below(v: &mut Value, drop_empty: bool) -> (usize, bool) {
+    match v {
+        Value::Mapping(m) if !m.is_empty() => {
+            let removed = prune(m, false, drop_empty);
+            (removed, m.is_empty())
+        }
+        Value::Sequence(seq) => {
+            let removed = seq.iter_mut().map(|v| prune_below(v, drop_empty).0);
+            (removed.sum(), false)
+        }
+        Value::Tagged(t) => prune_below(&mut t.value, drop_empty),
+        _ => (0, false),
+    }
+}
+
+/// Recursively sorts mapping keys. Keys of different types, e.g. strings
+/// and numbers, still get a consistent if arbitrary order.
+fn sort_keys(v: &mut Value) {
+    match v {
+        Value::Mapping(m) => {
+            let mut entries: Vec<_> = std::mem::take(m).into_iter().collect();
+            entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            for (k, mut item) in entries {
+                sort_keys(&mut item);
+                m.insert(k, item);
+            }
+        }
+        Value::Sequence(seq) => seq.iter_mut().for_each(sort_keys),
+        Value::Tagged(t) => sort_keys(&mut t.value),
+        _ => {}
+    }
+}
+
+/// Parses a whole document, which must be a mapping. An empty document is
+/// treated as an empty mapping.
+fn parse_root(format: Format, s: &str) -> Result<Value, Error> {
+    into_root(format.parse(s)?)
+}
+
+/// Checks that `v` can be a config root, turning null, e.g. from an empty
+/// document, into an empty mapping.
+fn into_root(v: Value) -> Result<Value, Error> {
+    match v {
+        Value::Null => Ok(Value::Mapping(Mapping::new())),
+        v @ Value::Mapping(_) => Ok(v),
+        _ => Err(Error::RootNotMapping),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::{
+        Change, Config, ConfigBuilder, Error as ConfigError, Format, LoadOutcome, MergeStrategy,
+        Migration,
+    };
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Test1;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Test2 {
+        field: usize,
+    }
+
+    #[test]
+    fn create_new() {
+        let mut c = Config::new();
+
+        c.add("test1", Test1 {}).unwrap();
+        c.add("test2", Test2 { field: 0 }).unwrap();
+
+        let temp: Test2 = c.get("test2").unwrap();
+        println!("{:#?}", temp);
+
+        let temp: Test1 = c.get("test1").unwrap();
+        println!("{:#?}", temp);
+    }
+
+    #[test]
+    fn nested_path() {
+        let mut c = Config::new();
+
+        c.add("database.connection.port", 5432).unwrap();
+        c.add("database.connection.host", "localhost".to_owned())
+            .unwrap();
+
+        assert_eq!(c.get::<u16>("database.connection.port").unwrap(), 5432);
+        assert_eq!(
+            c.get::<String>("database.connection.host").unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            c.get::<u16>("database.connection.user").unwrap_err(),
+            ConfigError::KeyNotFound("database.connection.user".to_owned())
+        );
+        assert!(c.get::<Test2>("database").is_err());
+    }
+
+    #[test]
+    fn nested_path_through_scalar() {
+        let mut c = Config::new();
+        c.add("database.port", 5432).unwrap();
+
+        assert_eq!(
+            c.add("database.port.number", 1).unwrap_err(),
+            ConfigError::NotAMapping("database.port".to_owned())
+        );
+        assert_eq!(
+            c.get::<u16>("database.port.number").unwrap_err(),
+            ConfigError::NotAMapping("database.port".to_owned())
+        );
+    }
+
+    #[test]
+    fn add_errors() {
+        #[derive(Deserialize, Debug)]
+        struct Unserializable;
+
+        impl serde::Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("boom"))
+            }
+        }
+
+        let mut c = Config::new();
+        match c.add("a.b", Unserializable) {
+            Err(ConfigError::SerializationFailed(e)) => {
+                assert!(e.starts_with("`a.b` ("), "{}", e);
+                assert!(e.contains("Unserializable"), "{}", e);
+                assert!(e.ends_with("): boom"), "{}", e);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!c.contains_key("a"));
+
+        let mut c = Config::new();
+        c.root = serde_yaml::Value::from(1);
+        assert_eq!(c.add("a", 1).unwrap_err(), ConfigError::RootNotMapping);
+    }
+
+    #[test]
+    fn set_and_try_add() {
+        let mut c = Config::new();
+        assert_eq!(c.set("db.port", 1).unwrap(), None);
+        assert_eq!(c.set("db.port", 2).unwrap(), Some(1));
+        assert_eq!(c.add("db.port", "two".to_owned()).unwrap(), None);
+        assert_eq!(c.add("db.port", 2).unwrap(), None);
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 2);
+
+        assert_eq!(
+            c.try_add("db.port", 3).unwrap_err(),
+            ConfigError::KeyExists("db.port".to_owned())
+        );
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 2);
+        c.try_add("db.host", "localhost".to_owned()).unwrap();
+        assert_eq!(c.get::<String>("db.host").unwrap(), "localhost");
+    }
+
+    #[test]
+    fn sequence_index() {
+        let mut c = Config::from_str("servers:\n- host: a\n- host: b\n").unwrap();
+        assert_eq!(c.get::<String>("servers.1.host").unwrap(), "b");
+        assert_eq!(
+            c.get::<String>("servers.2.host").unwrap_err(),
+            ConfigError::IndexOutOfBounds("servers.2".to_owned())
+        );
+        assert_eq!(
+            c.get::<String>("servers.first").unwrap_err(),
+            ConfigError::NotAMapping("servers".to_owned())
+        );
+
+        c.add("servers.0.port", 80).unwrap();
+        assert_eq!(c.get::<u16>("servers.0.port").unwrap(), 80);
+        assert_eq!(c.add("servers.0", "c".to_owned()).unwrap(), None);
+        assert_eq!(c.get::<String>("servers.0").unwrap(), "c");
+
+        c.push("servers", "d".to_owned()).unwrap();
+        assert_eq!(c.get::<String>("servers.2").unwrap(), "d");
+        c.push("clients", 1).unwrap();
+        c.push("clients", 2).unwrap();
+        assert_eq!(c.get::<Vec<u8>>("clients").unwrap(), vec![1, 2]);
+        assert_eq!(
+            c.push("servers.1.host", 1).unwrap_err(),
+            ConfigError::NotASequence("servers.1.host".to_owned())
+        );
+
+        assert!(c.remove("servers.0").unwrap());
+        assert_eq!(c.get::<String>("servers.0.host").unwrap(), "b");
+        assert!(!c.remove("servers.5").unwrap());
+    }
+
+    #[test]
+    fn remove() {
+        let mut c = Config::new();
+        c.add("test2", Test2 { field: 0 }).unwrap();
+        c.add("database.port", 5432).unwrap();
+
+        assert!(c.remove("test2").unwrap());
+        assert!(!c.remove("test2").unwrap());
+        assert!(c.get::<Test2>("test2").is_err());
+
+        assert!(c.remove("database.port").unwrap());
+        assert!(!c.remove("database.host").unwrap());
+        assert!(!c.remove("server.port").unwrap());
+        assert!(c.get::<u16>("database.port").is_err());
+    }
+
+    #[test]
+    fn get_or() {
+        let mut c = Config::new();
+        c.add("database.port", 5432).unwrap();
+        c.add("database.host", "localhost".to_owned()).unwrap();
+
+        assert_eq!(c.get_or("database.port", 1).unwrap(), 5432);
+        assert_eq!(c.get_or("database.timeout", 30).unwrap(), 30);
+        assert_eq!(c.get_or_else("database.timeout", || 30).unwrap(), 30);
+        assert!(c.get_or("database.host", 1).is_err());
+        assert_eq!(
+            c.get_or_else("database.port", || -> u16 { panic!("not called") })
+                .unwrap(),
+            5432
+        );
+    }
+
+    #[test]
+    fn get_or_insert() {
+        let mut c = Config::new();
+        c.add("database.port", 5432).unwrap();
+        c.add("database.user", Option::<String>::None).unwrap();
+
+        assert_eq!(c.get_or_insert("database.port", 1).unwrap(), 5432);
+        assert_eq!(c.get_or_insert("database.timeout", 30).unwrap(), 30);
+        assert_eq!(c.get::<u32>("database.timeout").unwrap(), 30);
+
+        assert_eq!(
+            c.get_or_insert("database.user", Some("admin".to_owned()))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            c.get_or_insert_replacing_null("database.user", Some("admin".to_owned()))
+                .unwrap(),
+            Some("admin".to_owned())
+        );
+        assert_eq!(c.get::<String>("database.user").unwrap(), "admin");
+    }
+
+    #[test]
+    fn env_overrides() {
+        std::env::set_var("TUNEUP_EO_DB__PORT", "5432");
+        std::env::set_var("TUNEUP_EO_DB__HOST", "localhost");
+        std::env::set_var("TUNEUP_EO_DEBUG", "true");
+        std::env::set_var("TUNEUP_EO_LIST", "[1, 2]");
+        std::env::set_var("TUNEUP_EOX", "ignored");
+
+        let mut c = Config::new();
+        c.add("db.port", 1).unwrap();
+        c.add("db.user", "admin".to_owned()).unwrap();
+        c.apply_env_overrides("TUNEUP_EO").unwrap();
+
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 5432);
+        assert_eq!(c.get::<String>("db.host").unwrap(), "localhost");
+        assert_eq!(c.get::<String>("db.user").unwrap(), "admin");
+        assert!(c.get::<bool>("debug").unwrap());
+        assert_eq!(c.get::<String>("list").unwrap(), "[1, 2]");
+        assert!(!c.contains_key("x"));
+    }
+
+    #[test]
+    fn merge() {
+        let mut base = Config::new();
+        base.add("db.port", 5432).unwrap();
+        base.add("db.host", "localhost".to_owned()).unwrap();
+        base.add("servers", vec!["a".to_owned()]).unwrap();
+        base.add("log", "info".to_owned()).unwrap();
+
+        let mut overlay = Config::new();
+        overlay.add("db.port", 6432).unwrap();
+        overlay.add("servers", vec!["b".to_owned()]).unwrap();
+        overlay.add("log.level", "debug".to_owned()).unwrap();
+
+        let mut c = Config::new();
+        c.merge(&base);
+        c.merge(&overlay);
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 6432);
+        assert_eq!(c.get::<String>("db.host").unwrap(), "localhost");
+        assert_eq!(c.get::<Vec<String>>("servers").unwrap(), vec!["b"]);
+        assert_eq!(c.get::<String>("log.level").unwrap(), "debug");
+
+        base.merge_with(&overlay, MergeStrategy::Append);
+        assert_eq!(base.get::<u16>("db.port").unwrap(), 6432);
+        assert_eq!(base.get::<Vec<String>>("servers").unwrap(), vec!["a", "b"]);
+
+        assert_eq!(
+            base.merge_value(&serde_yaml::Value::from(1), MergeStrategy::Overwrite)
+                .unwrap_err(),
+            ConfigError::RootNotMapping
+        );
+    }
+
+    #[test]
+    fn whole_root() {
+        #[derive(Serialize, Deserialize)]
+        struct Settings {
+            test2: Test2,
+            name: String,
+        }
+
+        let mut c = Config::new();
+        let settings = Settings {
+            test2: Test2 { field: 5 },
+            name: "app".to_owned(),
+        };
+        c.set_root(&settings).unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 5);
+
+        c.add("name", "renamed".to_owned()).unwrap();
+        let settings: Settings = c.deserialize_root().unwrap();
+        assert_eq!(settings.name, "renamed");
+
+        assert_eq!(
+            c.set_root(&vec![1]).unwrap_err(),
+            ConfigError::RootNotMapping
+        );
+        assert!(c.deserialize_root::<Vec<u8>>().is_err());
+    }
+
+    #[test]
+    fn one_way_types() {
+        #[derive(Deserialize)]
+        struct ReadOnly {
+            field: usize,
+        }
+        #[derive(Serialize)]
+        struct WriteOnly {
+            field: usize,
+        }
+
+        let mut c = Config::new();
+        c.try_add("a", WriteOnly { field: 1 }).unwrap();
+        c.push("b", WriteOnly { field: 2 }).unwrap();
+        assert_eq!(c.get::<ReadOnly>("a").unwrap().field, 1);
+        assert_eq!(c.get::<ReadOnly>("b.0").unwrap().field, 2);
+    }
+
+    #[test]
+    fn builder_layers() {
+        #[derive(Serialize)]
+        struct Defaults {
+            host: String,
+            port: u16,
+            debug: bool,
+        }
+
+        let test_file = "config-test-builder.yaml";
+        fs::write(test_file, "port: 8080\ndebug: true\n").unwrap();
+        std::env::set_var("TUNEUP_BUILDER_TEST_DEBUG", "false");
+
+        let c = ConfigBuilder::new()
+            .add_defaults(&Defaults {
+                host: "localhost".to_owned(),
+                port: 80,
+                debug: false,
+            })
+            .add_file(test_file)
+            .add_file_optional("config-test-builder-missing.yaml")
+            .add_env("TUNEUP_BUILDER_TEST")
+            .build()
+            .unwrap();
+        assert_eq!(c.get::<String>("host").unwrap(), "localhost");
+        assert_eq!(c.get::<u16>("port").unwrap(), 8080);
+        assert!(!c.get::<bool>("debug").unwrap());
+        assert_eq!(
+            c.path(),
+            Some(Path::new("config-test-builder-missing.yaml"))
+        );
+
+        let r = ConfigBuilder::new()
+            .add_file("config-test-builder-missing.yaml")
+            .build();
+        assert!(matches!(r, Err(ConfigError::FileNotFound(_))));
+        let r = ConfigBuilder::new().add_defaults(&1).build();
+        assert_eq!(r.err(), Some(ConfigError::RootNotMapping));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn get_opt() {
+        let mut c = Config::new();
+        c.add("a.b", 1).unwrap();
+        assert_eq!(c.get_opt::<u8>("a.b").unwrap(), Some(1));
+        assert_eq!(c.get_opt::<u8>("a.c").unwrap(), None);
+        assert_eq!(c.get_opt::<u8>("x.y").unwrap(), None);
+        assert!(matches!(
+            c.get_opt::<Test2>("a.b"),
+            Err(ConfigError::DeserializationFailed { key, .. }) if key == "a.b"
+        ));
+    }
+
+    #[test]
+    fn expand_env() {
+        std::env::set_var("TUNEUP_EXPAND_TEST_HOST", "example.com");
+        let mut c = Config::from_str(
+            "url: https://${TUNEUP_EXPAND_TEST_HOST}/\nlist: [$TUNEUP_EXPAND_TEST_HOST, $$x]\nn: 1\n",
+        )
+        .unwrap();
+        c.expand_env().unwrap();
+        assert_eq!(c.get::<String>("url").unwrap(), "https://example.com/");
+        assert_eq!(
+            c.get::<Vec<String>>("list").unwrap(),
+            vec!["example.com".to_owned(), "$x".to_owned()]
+        );
+
+        let mut c = Config::new();
+        c.add("missing", "$TUNEUP_EXPAND_TEST_UNSET".to_owned())
+            .unwrap();
+        c.expand_env().unwrap();
+        assert_eq!(
+            c.get::<String>("missing").unwrap(),
+            "$TUNEUP_EXPAND_TEST_UNSET"
+        );
+        assert_eq!(
+            c.expand_env_strict(),
+            Err(ConfigError::UndefinedEnvVar(
+                "TUNEUP_EXPAND_TEST_UNSET".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let old = Config::from_str("a: 1\nb:\n  c: x\n  d: [1]\ne: 2\n").unwrap();
+        let new = Config::from_str("a: 1\nb:\n  c: y\n  d: [1, 2]\nf: {g: 3}\n").unwrap();
+        let changes = old.diff(&new);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Modified {
+                    path: "b.c".to_owned(),
+                    old: "x".into(),
+                    new: "y".into(),
+                },
+                Change::Modified {
+                    path: "b.d".to_owned(),
+                    old: vec![1].into(),
+                    new: vec![1, 2].into(),
+                },
+                Change::Removed {
+                    path: "e".to_owned(),
+                    value: 2.into(),
+                },
+                Change::Added {
+                    path: "f".to_owned(),
+                    value: new.get::<serde_yaml::Value>("f").unwrap(),
+                },
+            ]
+        );
+        assert_eq!(changes[3].path(), "f");
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn diff_file() {
+        let test_file = "config-test-diff-file.yaml";
+        let mut c = Config::new().with_file(test_file);
+        c.add("a", 1).unwrap();
+        assert_eq!(
+            c.diff_file(),
+            Ok(vec![Change::Added {
+                path: "a".to_owned(),
+                value: 1.into(),
+            }])
+        );
+
+        c.write_to_file().unwrap();
+        assert_eq!(c.diff_file(), Ok(Vec::new()));
+
+        c.add("a", 2).unwrap();
+        assert_eq!(
+            c.diff_file().unwrap(),
+            vec![Change::Modified {
+                path: "a".to_owned(),
+                old: 1.into(),
+                new: 2.into(),
+            }]
+        );
+        assert_eq!(c.get::<usize>("a"), Ok(2));
+        assert!(c.is_dirty());
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn max_size() {
+        let test_file = "config-test-max-size.yaml";
+        fs::write(test_file, "a: 1\nb: 2\n").unwrap();
+
+        let mut c = Config::new().with_file(test_file).with_max_size(10);
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<u8>("b").unwrap(), 2);
+
+        let mut c = Config::new().with_file(test_file).with_max_size(9);
+        assert_eq!(
+            c.read_from_file(),
+            Err(ConfigError::FileTooLarge {
+                path: test_file.to_owned(),
+                limit: 9,
+            })
+        );
+        assert!(c.is_empty());
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn clone_and_compare() {
+        let mut c = Config::from_str("a: 1\n").unwrap();
+        let snapshot = c.clone();
+        assert_eq!(c, snapshot);
+        c.add("a", 2).unwrap();
+        assert_ne!(c, snapshot);
+        assert_eq!(snapshot.get::<u8>("a").unwrap(), 1);
+    }
+
+    #[test]
+    fn merge_file() {
+        let base = "config-test-merge-base.yaml";
+        let local = "config-test-merge-local.json";
+        fs::write(base, "db:\n  host: localhost\n  port: 5432\n").unwrap();
+        fs::write(local, r#"{"db": {"port": 6543}}"#).unwrap();
+
+        let mut c = Config::new().with_file(base);
+        c.read_from_file().unwrap();
+        c.merge_file(local).unwrap();
+        assert_eq!(c.get::<String>("db.host").unwrap(), "localhost");
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 6543);
+        assert_eq!(c.path(), Some(Path::new(base)));
+
+        assert!(c.merge_file("config-test-merge-missing.yaml").is_err());
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 6543);
+
+        for f in &[base, local] {
+            if let Err(e) = fs::remove_file(f) {
+                println!("{:#?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn raw() {
+        let c = Config::from_str("a:\n  b: [1, 2]\n'a.b': x\n").unwrap();
+        assert_eq!(c.raw("a.b"), Some(&"x".into()));
+        assert_eq!(c.raw_path("a.b"), Some(&vec![1, 2].into()));
+        assert_eq!(c.raw_path("a.b.1"), Some(&2.into()));
+        assert!(c.raw("b").is_none());
+        assert!(c.raw_path("a.b.c").is_none());
+    }
+
+    #[test]
+    fn dirty() {
+        let test_file = "config-test-dirty.yaml";
+        let mut c = Config::new().with_file(test_file);
+        assert!(!c.is_dirty());
+        c.add("a", 1).unwrap();
+        assert!(c.is_dirty());
+        c.save_if_dirty().unwrap();
+        assert!(!c.is_dirty());
+
+        fs::write(test_file, "a: 2\n").unwrap();
+        c.save_if_dirty().unwrap();
+        assert_eq!(fs::read_to_string(test_file).unwrap(), "a: 2\n");
+
+        c.read_from_file().unwrap();
+        assert!(!c.remove("b").unwrap());
+        assert!(!c.is_dirty());
+        assert!(c.remove("a").unwrap());
+        assert!(c.is_dirty());
+        c.save().unwrap();
+        assert!(!c.is_dirty());
+        assert_eq!(fs::read_to_string(test_file).unwrap(), "{}\n");
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn autosave() {
+        let test_file = "config-test-autosave.yaml";
+        {
+            let mut c = Config::new().with_file(test_file).with_autosave(true);
+            c.add("a", 1).unwrap();
+        }
+        assert_eq!(fs::read_to_string(test_file).unwrap(), "a: 1\n");
+
+        fs::write(test_file, "a: 2\n").unwrap();
+        {
+            let mut c = Config::new().with_file(test_file).with_autosave(true);
+            c.read_from_file().unwrap();
+        }
+        assert_eq!(fs::read_to_string(test_file).unwrap(), "a: 2\n");
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn entries() {
+        let c = Config::from_str("a: 1\n2: x\nb: [y]\n").unwrap();
+        let entries: Vec<_> = c.entries().collect();
+        assert_eq!(entries, vec![("a", &1.into()), ("b", &vec!["y"].into())]);
+    }
+
+    #[test]
+    fn flatten() {
+        let c = Config::from_str(
+            "db:\n  connection:\n    port: 5432\nservers:\n- host: a\n- host: b\nempty: {}\n",
+        )
+        .unwrap();
+        let flat = c.flatten();
+        assert_eq!(flat.len(), 4);
+        assert_eq!(flat["db.connection.port"], serde_yaml::Value::from(5432));
+        assert_eq!(flat["servers.1.host"], serde_yaml::Value::from("b"));
+        assert!(flat["empty"].as_mapping().unwrap().is_empty());
+
+        let rebuilt = Config::from_flat(flat).unwrap();
+        assert_eq!(rebuilt.flatten(), c.flatten());
+        assert!(rebuilt.raw("servers").unwrap().is_sequence());
+
+        let conflicting = vec![("a".to_owned(), 1.into()), ("a.b".to_owned(), 2.into())];
+        assert!(Config::from_flat(conflicting).is_err());
+    }
+
+    #[test]
+    fn rename_key() {
+        let mut c = Config::from_str("a: 1\nb:\n  c: 2\n  d: 3\ne: 4\n").unwrap();
+        c.rename_key("a", "z").unwrap();
+        c.rename_key("b.c", "b.y").unwrap();
+        assert_eq!(c.to_string().unwrap(), "z: 1\nb:\n  y: 2\n  d: 3\ne: 4\n");
+
+        c.rename_key("b.d", "f.g").unwrap();
+        assert_eq!(c.get::<u8>("f.g").unwrap(), 3);
+        assert!(!c.contains_key("b.d"));
+
+        assert_eq!(
+            c.rename_key("missing", "x"),
+            Err(ConfigError::KeyNotFound("missing".to_owned()))
+        );
+        assert_eq!(
+            c.rename_key("z", "e"),
+            Err(ConfigError::KeyExists("e".to_owned()))
+        );
+    }
+
+    #[test]
+    fn migrate() {
+        fn v1(c: &mut Config) -> Result<(), ConfigError> {
+            c.rename_key("host", "server.host")
+        }
+        fn v2(c: &mut Config) -> Result<(), ConfigError> {
+            c.add("server.port", 80).map(|_| ())
+        }
+        let migrations: &[Migration] = &[(1, 2, v2), (0, 1, v1)];
+
+        let mut c = Config::from_str("host: localhost\n").unwrap();
+        c.migrate(migrations).unwrap();
+        assert_eq!(c.get::<String>("server.host").unwrap(), "localhost");
+        assert_eq!(c.get::<u16>("server.port").unwrap(), 80);
+        assert_eq!(c.get::<u32>("version").unwrap(), 2);
+
+        let mut c = Config::from_str("v: 1\nserver: {}\n")
+            .unwrap()
+            .with_version_key("v");
+        c.migrate(migrations).unwrap();
+        assert_eq!(c.get::<u32>("v").unwrap(), 2);
+        assert!(!c.contains_key("version"));
+
+        let mut c = Config::from_str("version: 3\n").unwrap();
+        assert_eq!(
+            c.migrate(migrations),
+            Err(ConfigError::ConfigVersionTooNew {
+                found: 3,
+                supported: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn get_all() {
+        let c = Config::from_str("features:\n  a: true\n  b: false\nlist: [1]\n").unwrap();
+        let features = c.get_all::<bool>("features").unwrap();
+        assert_eq!(features.len(), 2);
+        assert!(features["a"]);
+        assert!(!features["b"]);
+
+        assert_eq!(
+            c.get_all::<bool>("list"),
+            Err(ConfigError::NotAMapping("list".to_owned()))
+        );
+        assert_eq!(
+            c.get_all::<bool>("missing"),
+            Err(ConfigError::KeyNotFound("missing".to_owned()))
+        );
+        assert!(c.get_all::<u8>("features").is_err());
+    }
+
+    #[test]
+    fn search_paths() {
+        let test_file = "config-test-search.yaml";
+        fs::write(test_file, "a: 1\n").unwrap();
+
+        let mut c =
+            Config::new().with_search_paths(&["config-test-search-missing.yaml", test_file]);
+        c.read_first_existing().unwrap();
+        assert_eq!(c.get::<u8>("a").unwrap(), 1);
+        assert_eq!(c.path(), Some(Path::new(test_file)));
+
+        let mut c = Config::new()
+            .with_search_paths(&["config-test-search-1.yaml", "config-test-search-2.yaml"]);
+        let e = c.read_first_existing().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "no config file found, tried `config-test-search-1.yaml`, `config-test-search-2.yaml`"
+        );
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn exists() {
+        let test_file = "config-test-exists.yaml";
+        let mut c = Config::new().with_file(test_file);
+        assert!(!c.exists());
+        assert_eq!(c.exists_result(), Ok(false));
+        c.write_to_file().unwrap();
+        assert!(c.exists());
+
+        assert!(!Config::new().exists());
+        assert_eq!(Config::new().exists_result(), Err(ConfigError::FileNotSet));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn path_separator() {
+        let mut c = Config::new().with_path_separator('/');
+        c.add("hosts/example.com/port", 80).unwrap();
+        assert_eq!(c.get::<u16>("hosts/example.com/port").unwrap(), 80);
+        assert!(c.raw("hosts").unwrap()["example.com"].is_mapping());
+        assert!(c.contains_key("hosts/example.com"));
+
+        c.apply_overrides("APP", vec![("APP_HOSTS__LOCAL".to_owned(), "1".to_owned())])
+            .unwrap();
+        assert_eq!(c.get::<u8>("hosts/local").unwrap(), 1);
+        assert!(c.flatten().contains_key("hosts/example.com/port"));
+
+        assert!(c.remove("hosts/example.com").unwrap());
+        assert!(!c.contains_key("hosts/example.com"));
+    }
+
+    #[test]
+    fn from_into_value() {
+        let v: serde_yaml::Value = serde_yaml::from_str("a: {b: 1}").unwrap();
+        let c = Config::from_value(v.clone()).unwrap();
+        assert_eq!(c.get::<u8>("a.b").unwrap(), 1);
+        assert_eq!(c.path(), None);
+        assert_eq!(c.into_value(), v);
+
+        assert!(Config::from_value(serde_yaml::Value::Null)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Config::from_value(vec![1].into()),
+            Err(ConfigError::RootNotMapping)
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let c = Config::from_str("Server:\n  PORT: 80\n  port: 81\n").unwrap();
+        assert!(!c.contains_key("server.port"));
+
+        let c = c.with_case_insensitive(true);
+        assert!(c.contains_key("server.port"));
+        assert_eq!(c.get::<u16>("SERVER.Port").unwrap(), 80);
+        assert_eq!(c.get_all::<u16>("server").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parse() {
+        use std::convert::TryFrom;
+
+        let c: Config = "a: 1\n".parse().unwrap();
+        assert_eq!(c.get::<u8>("a").unwrap(), 1);
+        let c = Config::try_from("a: 2\n".to_owned()).unwrap();
+        assert_eq!(c.get::<u8>("a").unwrap(), 2);
+        assert_eq!(Config::try_from("- 1"), Err(ConfigError::RootNotMapping));
+    }
+
+    #[test]
+    fn apply_defaults() {
+        #[derive(Serialize)]
+        struct Defaults {
+            host: String,
+            port: u16,
+            tags: Vec<String>,
+        }
+
+        let mut c = Config::from_str("port: 8080\ntags: [a]\nextra: 1\n").unwrap();
+        c.apply_defaults(&Defaults {
+            host: "localhost".to_owned(),
+            port: 80,
+            tags: vec!["b".to_owned(), "c".to_owned()],
+        })
+        .unwrap();
+        assert_eq!(c.get::<String>("host").unwrap(), "localhost");
+        assert_eq!(c.get::<u16>("port").unwrap(), 8080);
+        assert_eq!(c.get::<Vec<String>>("tags").unwrap(), vec!["a".to_owned()]);
+        assert_eq!(c.get::<u8>("extra").unwrap(), 1);
+
+        assert_eq!(c.apply_defaults(&1), Err(ConfigError::RootNotMapping));
+    }
+
+    #[test]
+    fn strict_extension() {
+        let test_file = "config-test-strict-ext.yaml";
+        let mut c = Config::new()
+            .with_file(test_file)
+            .with_format(Format::Json)
+            .with_strict_extension(true);
+        assert_eq!(
+            c.write_to_file(),
+            Err(ConfigError::ExtensionFormatMismatch {
+                path: test_file.to_owned(),
+                format: Format::Json,
+            })
+        );
+        assert!(!Path::new(test_file).exists());
+
+        let mut c = c.with_format(Format::Yaml);
+        c.write_to_file().unwrap();
+        c.read_from_file().unwrap();
+
+        let mut c = Config::new()
+            .with_file("config-test-strict-ext.conf")
+            .with_format(Format::Json)
+            .with_strict_extension(true);
+        assert!(matches!(
+            c.read_from_file(),
+            Err(ConfigError::FileNotFound(_))
+        ));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn with_value() {
+        let c = Config::new()
+            .with_value("port", 8080)
+            .with_value("server.host", "0.0.0.0");
+        assert_eq!(c.get::<u16>("port"), Ok(8080));
+        assert_eq!(c.get::<String>("server.host"), Ok("0.0.0.0".to_owned()));
+
+        assert!(matches!(
+            c.try_with_value("port.number", 1),
+            Err(ConfigError::NotAMapping(_))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't set `port.number`")]
+    fn with_value_panics() {
+        let _ = Config::new()
+            .with_value("port", 8080)
+            .with_value("port.number", 1);
+    }
+
+    #[test]
+    fn load_or_create() {
+        let test_file = "config-test-load-or-create.yaml";
+        let defaults = Test2 { field: 1 };
+
+        let mut c = Config::new().with_file(test_file);
+        assert_eq!(c.load_or_create(&defaults), Ok(LoadOutcome::Created));
+        assert_eq!(c.get::<usize>("field"), Ok(1));
+        assert!(!c.is_dirty());
+
+        c.add("field", 2).unwrap();
+        c.write_to_file().unwrap();
+
+        let mut c = Config::new().with_file(test_file);
+        assert_eq!(c.load_or_create(&defaults), Ok(LoadOutcome::Loaded));
+        assert_eq!(c.get::<usize>("field"), Ok(2));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn set_many() {
+        let mut c = Config::new();
+        let ports = (0..3).map(|i| (format!("ports.p{}", i), 8000 + i));
+        c.set_many(ports).unwrap();
+        assert_eq!(c.get::<u16>("ports.p2"), Ok(8002));
+
+        let bad = vec![("a".to_owned(), 1), ("ports.p0.x".to_owned(), 2)];
+        assert!(matches!(c.set_many(bad), Err(ConfigError::NotAMapping(_))));
+        assert!(!c.contains_key("a"));
+
+        struct Flaky(bool);
+
+        impl serde::Serialize for Flaky {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                match self.0 {
+                    true => s.serialize_bool(true),
+                    false => Err(serde::ser::Error::custom("boom")),
+                }
+            }
+        }
+
+        let bad = vec![
+            ("a".to_owned(), Flaky(true)),
+            ("b".to_owned(), Flaky(false)),
+        ];
+        match c.set_many(bad) {
+            Err(ConfigError::SerializationFailed(e)) => assert!(e.starts_with("`b`"), "{}", e),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!c.contains_key("a"));
+    }
+
+    #[test]
+    fn header_comment() {
+        let c = Config::new()
+            .with_header_comment("Managed by tuneup\n\nedit with care")
+            .with_value("a", 1);
+        let mut out = Vec::new();
+        c.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# Managed by tuneup\n#\n# edit with care\n\na: 1\n"
+        );
+
+        for format in &[Format::Toml, Format::Json] {
+            let mut out = Vec::new();
+            let mut c = c.clone().with_format(*format);
+            c.write_to(&mut out).unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&out).starts_with('#'),
+                *format == Format::Toml
+            );
+            c.read_from(&out[..]).unwrap();
+            assert_eq!(c.get::<i32>("a"), Ok(1));
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let mut c = Config::new();
+        c.add_bytes("tls.key", b"\x00\xffsecret").unwrap();
+        assert_eq!(c.get::<String>("tls.key"), Ok("AP9zZWNyZXQ=".to_owned()));
+        assert_eq!(c.get_bytes("tls.key"), Ok(b"\x00\xffsecret".to_vec()));
+
+        c.add("tls.key", "not base64!".to_owned()).unwrap();
+        assert_eq!(
+            c.get_bytes("tls.key"),
+            Err(ConfigError::DeserializationFailed {
+                key: "tls.key".to_owned(),
+                message: "invalid base64 length".to_owned()
+            })
+        );
+        assert!(matches!(
+            c.get_bytes("tls.cert"),
+            Err(ConfigError::KeyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn subsection() {
+        let mut c = Config::from_str("db:\n  host: localhost\n  port: 5432\nname: app\n").unwrap();
+        let mut db = c.subsection("db").unwrap();
+        assert_eq!(db.path(), None);
+        assert_eq!(db.get::<u16>("port"), Ok(5432));
+        assert!(!db.contains_key("name"));
+
+        db.add("port", 5433).unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5432));
+        c.splice_subsection("db", &db).unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5433));
+
+        assert_eq!(
+            c.subsection("name").err(),
+            Some(ConfigError::NotAMapping("name".to_owned()))
+        );
+        assert_eq!(
+            c.subsection("cache").err(),
+            Some(ConfigError::KeyNotFound("cache".to_owned()))
+        );
+    }
+
+    #[test]
+    fn merge_env_file() {
+        let test_file = "config-test-merge-env.env";
+        let content = "# deploy overrides\nAPP_DB__PORT=5433\n\n  APP_NAME = prod app \nOTHER=1\n";
+        fs::write(test_file, content).unwrap();
+
+        let mut c = Config::from_str("db:\n  host: localhost\n  port: 5432\n").unwrap();
+        c.merge_env_file(test_file, "APP").unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5433));
+        assert_eq!(c.get::<String>("db.host"), Ok("localhost".to_owned()));
+        assert_eq!(c.get::<String>("name"), Ok("prod app".to_owned()));
+        assert!(!c.contains_key("other"));
+
+        fs::write(test_file, "APP_A=1\nAPP_B\n").unwrap();
+        assert_eq!(
+            c.merge_env_file(test_file, "APP"),
+            Err(ConfigError::DeserializationFailed {
+                key: String::new(),
+                message: format!("`{}` line 2: expected KEY=VALUE", test_file)
+            })
+        );
+        assert!(matches!(
+            c.merge_env_file("config-test-merge-env-missing.env", "APP"),
+            Err(ConfigError::FileNotFound(_))
+        ));
+
+        fs::write(test_file, "DB__PORT=5434\n_X=1\n").unwrap();
+        c.merge_env_file(test_file, "").unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5434));
+        assert_eq!(c.get::<u8>("_x"), Ok(1));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn update() {
+        let mut c = Config::from_str("hosts: [a]\n").unwrap();
+        c.update("hosts", |hosts: &mut Vec<String>| {
+            hosts.push("b".to_owned())
+        })
+        .unwrap();
+        assert_eq!(
+            c.get::<Vec<String>>("hosts"),
+            Ok(vec!["a".to_owned(), "b".to_owned()])
+        );
+
+        assert_eq!(
+            c.update("retries", |n: &mut u32| *n += 1),
+            Err(ConfigError::KeyNotFound("retries".to_owned()))
+        );
+        c.update_or_default("retries", |n: &mut u32| *n += 1)
+            .unwrap();
+        c.update_or_default("retries", |n: &mut u32| *n += 1)
+            .unwrap();
+        assert_eq!(c.get::<u32>("retries"), Ok(2));
+
+        let c = Config::from_str("Port: 1\n").unwrap();
+        let mut c = c.with_case_insensitive(true);
+        c.update("port", |p: &mut u16| *p += 10).unwrap();
+        c.update_or_default("PORT", |p: &mut u16| *p += 10).unwrap();
+        assert_eq!(c.keys(), vec!["Port"]);
+        assert_eq!(c.get::<u16>("Port"), Ok(21));
+    }
+
+    #[test]
+    fn check_known_keys() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Server {
+            #[serde(alias = "hostname")]
+            host: String,
+            #[serde(rename = "listen-port")]
+            port: u16,
+        }
+
+        let c = Config::from_str("hostname: a\nprot: 1\nlisten-port: 2\nport: 3\n").unwrap();
+        assert_eq!(
+            c.check_known_keys::<Server>(),
+            Err(vec!["prot".to_owned(), "port".to_owned()])
+        );
+        let c = Config::from_str("host: a\nlisten-port: 2\n").unwrap();
+        assert_eq!(c.check_known_keys::<Server>(), Ok(()));
+        assert_eq!(
+            c.check_known_keys::<std::collections::HashMap<String, String>>(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn write_preview() {
+        let test_file = "config-test-preview.yaml";
+        let mut c = Config::new()
+            .with_file(test_file)
+            .with_sorted_keys(true)
+            .with_header_comment("generated")
+            .with_value("b", 2)
+            .with_value("a", 1);
+        let preview = c.write_preview().unwrap();
+        assert_eq!(preview, "# generated\n\na: 1\nb: 2\n");
+        assert!(!Path::new(test_file).exists());
+        assert!(c.is_dirty());
+
+        c.write_to_file().unwrap();
+        assert_eq!(fs::read_to_string(test_file).unwrap(), preview);
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn scalar_getters() {
+        let c = Config::from_str("name: app\nport: 8080\noffset: -3\nratio: 0.5\ndebug: true\n")
+            .unwrap();
+        assert_eq!(c.get_str("name"), Ok("app".to_owned()));
+        assert_eq!(c.get_i64("offset"), Ok(-3));
+        assert_eq!(c.get_u64("port"), Ok(8080));
+        assert_eq!(c.get_f64("ratio"), Ok(0.5));
+        assert_eq!(c.get_f64("port"), Ok(8080.0));
+        assert_eq!(c.get_bool("debug"), Ok(true));
+
+        let mismatch = |key: &str, message: &str| {
+            Some(ConfigError::DeserializationFailed {
+                key: key.to_owned(),
+                message: message.to_owned(),
+            })
+        };
+        assert_eq!(
+            c.get_i64("name").err(),
+            mismatch("name", "expected integer, found string")
+        );
+        assert_eq!(
+            c.get_str("port").err(),
+            mismatch("port", "expected string, found integer")
+        );
+        assert_eq!(
+            c.get_u64("offset").err(),
+            mismatch(
+                "offset",
+                "expected non-negative integer, found negative integer"
+            )
+        );
+        assert_eq!(
+            c.get_bool("ratio").err(),
+            mismatch("ratio", "expected boolean, found float")
+        );
+        assert_eq!(
+            c.get_bool("verbose"),
+            Err(ConfigError::KeyNotFound("verbose".to_owned()))
+        );
+    }
+
+    #[test]
+    fn documented_keys() {
+        let test_file = "config-test-documented.yaml";
+        let mut c = Config::new()
+            .with_file(test_file)
+            .with_header_comment("generated");
+        c.add_documented("db.port", 5432, "Port of the primary")
+            .unwrap();
+        c.add("db.host", "localhost".to_owned()).unwrap();
+        c.write_to_file().unwrap();
+        assert_eq!(
+            fs::read_to_string(test_file).unwrap(),
+            "# generated\n\ndb:\n  # Port of the primary\n  port: 5432\n  host: localhost\n"
+        );
+
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+        assert_eq!(c.doc("db.port"), Some("Port of the primary"));
+        assert_eq!(c.doc("db.host"), None);
+        assert_eq!(c.doc("db"), None);
+
+        c.rename_key("db", "primary").unwrap();
+        assert_eq!(c.doc("primary.port"), Some("Port of the primary"));
+        assert_eq!(c.doc("db.port"), None);
+        c.remove("primary.port").unwrap();
+        c.add("primary.port", 5433).unwrap();
+        assert_eq!(c.doc("primary.port"), None);
+        c.write_to_file().unwrap();
+        assert_eq!(
+            fs::read_to_string(test_file).unwrap(),
+            "primary:\n  host: localhost\n  port: 5433\n"
+        );
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn apply_cli_overrides() {
+        let mut c = Config::from_str("db:\n  host: localhost\n").unwrap();
+        c.apply_cli_overrides(&["db.port=5432", "feature.enabled=true", "motd=a=b"])
+            .unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5432));
+        assert_eq!(c.get::<bool>("feature.enabled"), Ok(true));
+        assert_eq!(c.get::<String>("motd"), Ok("a=b".to_owned()));
+        assert_eq!(c.get::<String>("db.host"), Ok("localhost".to_owned()));
+
+        let args = vec!["a=1".to_owned(), "db.port".to_owned()];
+        assert_eq!(
+            c.apply_cli_overrides(&args),
+            Err(ConfigError::MalformedOverride("db.port".to_owned()))
+        );
+        assert!(!c.contains_key("a"));
+        assert_eq!(
+            c.apply_cli_overrides(&["=1"]).unwrap_err().to_string(),
+            "override `=1` is not of the form key=value"
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        let a = Config::from_str("db:\n  host: h\n  port: 1\nname: n\n").unwrap();
+        let b = Config::from_str("name: n\ndb:\n  port: 1\n  host: h\n").unwrap();
+        assert_eq!(a.checksum(), b.checksum());
+        assert_ne!(a.checksum(), b.clone().with_value("db.port", 2).checksum());
+        assert_eq!(Config::new().checksum(), 0xc735_ad19_83dc_7ddd);
+    }
+
+    #[test]
+    fn lenient_scalars() {
+        let yaml = "port: \"8080\"\ndebug: 'true'\nname: \"42\"\nratio: x\n";
+        let c = Config::from_str(yaml).unwrap();
+        assert!(c.get::<u16>("port").is_err());
+
+        let c = c.with_lenient_scalars(true);
+        assert_eq!(c.get::<u16>("port"), Ok(8080));
+        assert_eq!(c.get_bool("debug"), Ok(true));
+        assert_eq!(c.get::<String>("name"), Ok("42".to_owned()));
+        assert_eq!(c.get::<u8>("name"), Ok(42));
+        assert_eq!(
+            c.get::<f64>("ratio").err(),
+            Some(ConfigError::DeserializationFailed {
+                key: "ratio".to_owned(),
+                message: "invalid type: string \"x\", expected f64".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn is_file_stale() {
+        let test_file = "config-test-stale.yaml";
+        let mut c = Config::new().with_file(test_file).with_value("a", 1);
+        assert!(matches!(
+            c.is_file_stale(),
+            Err(ConfigError::FileNotFound(_))
+        ));
+
+        c.write_to_file().unwrap();
+        assert_eq!(c.is_file_stale(), Ok(false));
+
+        let mut other = Config::new().with_file(test_file);
+        assert_eq!(other.is_file_stale(), Ok(true));
+        other.read_from_file().unwrap();
+        assert_eq!(other.is_file_stale(), Ok(false));
+
+        fs::write(test_file, "a: 1\nb: 2\n").unwrap();
+        assert_eq!(other.is_file_stale(), Ok(true));
+        other.reload().unwrap();
+        assert_eq!(other.is_file_stale(), Ok(false));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+        assert!(matches!(
+            other.is_file_stale(),
+            Err(ConfigError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn merge_str() {
+        let mut c = Config::from_str("db:\n  host: localhost\n  port: 5432\n").unwrap();
+        c.merge_str("db:\n  port: 5433\ndebug: true\n").unwrap();
+        assert_eq!(c.get::<u16>("db.port"), Ok(5433));
+        assert_eq!(c.get::<String>("db.host"), Ok("localhost".to_owned()));
+        assert_eq!(c.get::<bool>("debug"), Ok(true));
+
+        let before = c.clone();
+        assert_eq!(c.merge_str("- a\n"), Err(ConfigError::RootNotMapping));
+        assert!(matches!(
+            c.merge_str("a: ["),
+            Err(ConfigError::DeserializationFailed { .. })
+        ));
+        assert_eq!(c, before);
+    }
+
+    #[test]
+    fn iter_seq() {
+        let c = Config::from_str("ports: [80, http, 443]\nname: app\n").unwrap();
+        let ports: Vec<_> = c.iter_seq::<u16>("ports").unwrap().collect();
+        assert_eq!(ports[0], Ok(80));
+        assert!(matches!(
+            &ports[1],
+            Err(ConfigError::DeserializationFailed { key, .. }) if key == "ports.1"
+        ));
+        assert_eq!(ports[2], Ok(443));
+
+        assert!(matches!(
+            c.iter_seq::<u16>("name"),
+            Err(ConfigError::NotASequence(_))
+        ));
+        assert!(matches!(
+            c.iter_seq::<u16>("hosts"),
+            Err(ConfigError::KeyNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn readonly() {
+        let test_file = "config-test-readonly.yaml";
+        fs::write(test_file, "a: 1\n").unwrap();
+        let mut c = Config::new()
+            .with_file(test_file)
+            .with_readonly(true)
+            .with_autosave(true);
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<i32>("a"), Ok(1));
+
+        assert_eq!(c.add("a", 2), Err(ConfigError::ReadOnly));
+        assert_eq!(c.set("b", 2), Err(ConfigError::ReadOnly));
+        assert_eq!(c.remove("a"), Err(ConfigError::ReadOnly));
+        assert_eq!(c.rename_key("a", "b"), Err(ConfigError::ReadOnly));
+        assert_eq!(c.write_to_file(), Err(ConfigError::ReadOnly));
+        let layer = "config-test-readonly-layer.yaml";
+        fs::write(layer, "a: 3\n").unwrap();
+        assert_eq!(c.merge_file(layer), Err(ConfigError::ReadOnly));
+        c.merge(&Config::from_str("a: 4\n").unwrap());
+        c.clear();
+        assert_eq!(c.get::<i32>("a"), Ok(1));
+        assert!(!c.is_dirty());
+
+        drop(c);
+        assert_eq!(fs::read_to_string(test_file).unwrap(), "a: 1\n");
+
+        for test_file in &[test_file, layer] {
+            if let Err(e) = fs::remove_file(test_file) {
+                println!("{:#?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn export_env() {
+        let yaml = "db:\n  port: 5432\n  host: localhost\nhosts: [a, b]\nempty: {}\nunset: ~\n";
+        let c = Config::from_str(yaml).unwrap();
+        let vars: Vec<(&str, &str)> = vec![
+            ("APP_DB_HOST", "localhost"),
+            ("APP_DB_PORT", "5432"),
+            ("APP_EMPTY", "{}"),
+            ("APP_HOSTS_0", "a"),
+            ("APP_HOSTS_1", "b"),
+            ("APP_UNSET", ""),
+        ];
+        let expected: Vec<(String, String)> = vars
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        assert_eq!(c.export_env("APP"), Ok(expected.clone()));
+        assert_eq!(c.export_env("APP_"), Ok(expected));
+        assert_eq!(Config::new().export_env("APP"), Ok(Vec::new()));
+        assert_eq!(
+            c.export_env("").unwrap()[0],
+            ("DB_HOST".to_owned(), "localhost".to_owned())
+        );
+
+        let c = Config::from_str("db_port: 1\ndb:\n  port: 2\n").unwrap();
+        assert_eq!(
+            c.export_env("APP").unwrap_err().to_string(),
+            "config keys `db.port` and `db_port` both export as `APP_DB_PORT`"
+        );
+        let c = Config::from_str("port: 1\nPort: 2\n").unwrap();
+        assert_eq!(
+            c.export_env("APP"),
+            Err(ConfigError::EnvVarCollision {
+                name: "APP_PORT".to_owned(),
+                keys: ("Port".to_owned(), "port".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn take() {
+        let mut c = Config::from_str("bootstrap:\n  token: abc\n  port: 1\n").unwrap();
+        assert_eq!(c.take::<String>("bootstrap.token"), Ok("abc".to_owned()));
+        assert!(!c.contains_key("bootstrap.token"));
+        assert_eq!(
+            c.take::<String>("bootstrap.token"),
+            Err(ConfigError::KeyNotFound("bootstrap.token".to_owned()))
+        );
+
+        assert!(c.take::<Vec<u8>>("bootstrap.port").is_err());
+        assert_eq!(c.get::<u8>("bootstrap.port"), Ok(1));
+
+        let c = Config::from_str("Bootstrap:\n  Token: abc\n").unwrap();
+        let mut c = c.with_case_insensitive(true);
+        assert_eq!(c.take::<String>("bootstrap.token"), Ok("abc".to_owned()));
+        assert!(!c.contains_key("bootstrap.token"));
+        assert!(c.contains_key("bootstrap"));
+    }
+
+    #[test]
+    fn case_insensitive_writes() {
+        let c = Config::from_str("Port: 1\nDB:\n  Host: a\n").unwrap();
+        let mut c = c.with_case_insensitive(true);
+        assert_eq!(c.set("port", 99), Ok(Some(1)));
+        assert_eq!(c.get::<u16>("port"), Ok(99));
+        c.set("db.user", "u".to_owned()).unwrap();
+        assert_eq!(c.keys(), vec!["Port", "DB"]);
+        assert_eq!(c.get::<String>("DB.user"), Ok("u".to_owned()));
+
+        assert_eq!(c.remove("port"), Ok(true));
+        assert!(!c.contains_key("port"));
+        assert_eq!(c.remove("db.host"), Ok(true));
+        assert!(!c.contains_key("db.host"));
+    }
+
+    #[test]
+    fn file_lock() {
+        let test_file = "config-test-lock.yaml";
+        let lock_file = "config-test-lock.yaml.lock";
+        let mut c = Config::new()
+            .with_file(test_file)
+            .with_file_lock_timeout(Duration::from_millis(50))
+            .with_value("a", 1);
+        c.write_to_file().unwrap();
+
+        let held = fs::File::open(lock_file).unwrap();
+        held.lock_shared().unwrap();
+        c.read_from_file().unwrap();
+        assert_eq!(
+            c.write_to_file(),
+            Err(ConfigError::LockTimeout(test_file.to_owned()))
+        );
+        held.unlock().unwrap();
+        c.write_to_file().unwrap();
+
+        held.lock().unwrap();
+        assert_eq!(
+            c.read_from_file(),
+            Err(ConfigError::LockTimeout(test_file.to_owned()))
+        );
+        drop(held);
+        c.read_from_file().unwrap();
+
+        for f in &[test_file, lock_file] {
+            if let Err(e) = fs::remove_file(f) {
+                println!("{:#?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn anchors() {
+        let yaml =
+            "base: &base\n  retries: 3\n  timeout: 10\nprod:\n  db: *base\ndev:\n  db: *base\n";
+        let mut c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.get::<u32>("prod.db.retries"), Ok(3));
+        c.add("dev.debug", true).unwrap();
+
+        let expanded = c.write_preview().unwrap();
+        assert_eq!(expanded.matches("retries").count(), 3);
+
+        let c = c.with_anchors(true);
+        let shared = c.write_preview().unwrap();
+        assert_eq!(
+            shared,
+            "base: &base\n  retries: 3\n  timeout: 10\nprod:\n  db: *base\ndev:\n  db: *base\n  debug: true\n"
+        );
+        assert_eq!(Config::from_str(&shared).unwrap().root, c.root);
+
+        let text = "k:\n  x: 1\n  y: 2\ntail\n";
+        let c = Config::new()
+            .with_anchors(true)
+            .with_value("a", Config::from_str("x: 1\ny: 2\n").unwrap().into_value())
+            .with_value("b", text);
+        let written = c.write_preview().unwrap();
+        assert_eq!(
+            Config::from_str(&written).unwrap().get_str("b"),
+            Ok(text.to_owned())
+        );
+    }
+
+    #[test]
+    fn get_section() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(default)]
+        struct Pool {
+            size: u32,
+            timeout: u32,
+            hosts: Vec<String>,
+        }
+
+        impl Default for Pool {
+            fn default() -> Self {
+                Pool {
+                    size: 4,
+                    timeout: 30,
+                    hosts: vec!["localhost".to_owned()],
+                }
+            }
+        }
+
+        let c =
+            Config::from_str("db:\n  pool:\n    size: 16\n    hosts: [a]\n  cache: ~\n").unwrap();
+        assert_eq!(
+            c.get_section::<Pool>("db.pool"),
+            Ok(Pool {
+                size: 16,
+                timeout: 30,
+                hosts: vec!["a".to_owned()],
+            })
+        );
+        assert_eq!(c.get_section::<Pool>("db.cache"), Ok(Pool::default()));
+        assert_eq!(c.get_section::<Pool>("queue"), Ok(Pool::default()));
+    }
+
+    #[test]
+    fn tagged_values() {
+        let test_file = "config-test-tagged-values.yaml";
+        let content = "password: !secret hunter2\ndb: !env\n  host: x\n";
+        fs::write(test_file, content).unwrap();
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+
+        let (tag, value) = c.get_tagged("password").unwrap();
+        assert!(tag == "secret");
+        assert_eq!(tag.to_string(), "!secret");
+        assert_eq!(value, &serde_yaml::Value::from("hunter2"));
+        assert_eq!(c.get_str("password"), Ok("hunter2".to_owned()));
+        assert_eq!(c.get::<String>("db.host"), Ok("x".to_owned()));
+        assert!(c.get_tagged("db.host").is_none());
+        assert!(c.get_tagged("missing").is_none());
+
+        c.add("db.port", 5432).unwrap();
+        c.write_to_file().unwrap();
+        assert_eq!(
+            fs::read_to_string(test_file).unwrap(),
+            "password: !secret hunter2\ndb: !env\n  host: x\n  port: 5432\n"
+        );
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn open_and_create() {
+        let test_file = "config-test-open-and-create.yaml";
+        assert_eq!(
+            Config::open(test_file).err(),
+            Some(ConfigError::FileNotFound(test_file.to_owned()))
+        );
+
+        let mut c = Config::create(test_file);
+        assert_eq!(c.path(), Some(Path::new(test_file)));
+        assert!(c.keys().is_empty());
+        c.add("a", 1).unwrap();
+        c.write_to_file().unwrap();
+
+        let c = Config::open(test_file).unwrap();
+        assert_eq!(c.get::<usize>("a"), Ok(1));
+        assert_eq!(c.path(), Some(Path::new(test_file)));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn trailing_newline() {
+        let mut c = Config::new().with_format(Format::Json).with_pretty(false);
+        c.add("a", 1).unwrap();
+        assert_eq!(c.write_preview(), Ok("{\"a\":1}\n".to_owned()));
+        let c = c.with_trailing_newline(false);
+        assert_eq!(c.write_preview(), Ok("{\"a\":1}".to_owned()));
+
+        let mut c = Config::new();
+        c.add("a", "x\n\n".to_owned()).unwrap();
+        assert_eq!(c.write_preview(), Ok("a: |+\n  x\n\n".to_owned()));
+        let c = Config::from_str(&c.write_preview().unwrap()).unwrap();
+        assert_eq!(c.get_str("a"), Ok("x\n\n".to_owned()));
+    }
+
+    #[test]
+    fn get_json_string() {
+        let c =
+            Config::from_str("a:\n  1: x\n  b: !tag [true, null, 2.5]\nnan: .nan\nkey: {[1]: x}\n")
+                .unwrap();
+        assert_eq!(
+            c.get_json_string("a"),
+            Ok(r#"{"1":"x","b":[true,null,2.5]}"#.to_owned())
+        );
+        assert_eq!(c.get_json_string("a.b.0"), Ok("true".to_owned()));
+        assert!(matches!(
+            c.get_json_string("nan"),
+            Err(ConfigError::SerializationFailed(_))
+        ));
+        assert!(matches!(
+            c.get_json_string("key"),
+            Err(ConfigError::SerializationFailed(_))
+        ));
+        assert_eq!(
+            c.get_json_string("missing"),
+            Err(ConfigError::KeyNotFound("missing".to_owned()))
+        );
+    }
+
+    #[test]
+    fn prune_nulls() {
+        let yaml = "a: ~\nb:\n  c: ~\n  d: 1\ne:\n  f: ~\ng: {}\nh:\n- i: ~\n  j: 2\n- ~\n";
+        let mut c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.prune_nulls(false, false), Ok(4));
+        assert!(c.is_dirty());
+        assert_eq!(
+            c.to_string().unwrap(),
+            "b:\n  d: 1\ne: {}\ng: {}\nh:\n- j: 2\n- null\n"
+        );
+
+        let mut c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.prune_nulls(true, true), Ok(4));
+        assert_eq!(
+            c.to_string().unwrap(),
+            "a: null\nb:\n  d: 1\ng: {}\nh:\n- j: 2\n- null\n"
+        );
+
+        let mut c = Config::from_str("a: 1\n").unwrap();
+        assert_eq!(c.prune_nulls(false, true), Ok(0));
+        assert!(!c.is_dirty());
+    }
+
+    #[test]
+    fn required() {
+        let c = Config::from_str("db:\n  url: x\n  port: p\n").unwrap();
+        assert_eq!(c.required::<String>("db.url"), Ok("x".to_owned()));
+        let missing = c.required::<String>("db.user");
+        assert_eq!(
+            missing,
+            Err(ConfigError::RequiredKeyMissing("db.user".to_owned()))
+        );
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "required config key `db.user` is missing"
+        );
+        assert!(matches!(
+            c.required::<u16>("db.port"),
+            Err(ConfigError::DeserializationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn merge_all() {
+        let base = "config-test-merge-all-base.yaml";
+        let local = "config-test-merge-all-local.json";
+        let missing = "config-test-merge-all-missing.yaml";
+        let broken = "config-test-merge-all-broken.yaml";
+        fs::write(base, "db:\n  host: x\n  port: 1\nname: a\n").unwrap();
+        fs::write(local, r#"{"db": {"port": 2}}"#).unwrap();
+        fs::write(broken, "a: [\n").unwrap();
+
+        let mut c = Config::new();
+        assert_eq!(
+            c.merge_all(&[base, missing, local], false),
+            Err(ConfigError::FileNotFound(missing.to_owned()))
+        );
+        match c.merge_all(&[base, broken], true) {
+            Err(ConfigError::DeserializationFailed { key, message }) => {
+                assert_eq!(key, "");
+                assert!(message.starts_with("in `config-test-merge-all-broken.yaml`: "));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        fs::write(broken, "- a\n").unwrap();
+        assert_eq!(
+            c.merge_all(&[base, broken], true).unwrap_err().to_string(),
+            "failed to deserialize config: in `config-test-merge-all-broken.yaml`: \
+             config root is not a mapping"
+        );
+        assert!(c.is_empty());
+        assert_eq!(c.path(), None);
+
+        c.merge_all(&[base, local, missing], true).unwrap();
+        assert_eq!(c.get::<String>("db.host"), Ok("x".to_owned()));
+        assert_eq!(c.get::<usize>("db.port"), Ok(2));
+        assert_eq!(c.get::<String>("name"), Ok("a".to_owned()));
+        assert_eq!(c.path(), Some(Path::new(missing)));
+
+        for test_file in &[base, local, broken] {
+            if let Err(e) = fs::remove_file(test_file) {
+                println!("{:#?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn eq_ignores_bookkeeping() {
+        let test_file = "config-test-eq-ignores-bookkeeping.yaml";
+        let c = Config::from_str("# the a\na: 1\n")
+            .unwrap()
+            .with_file(test_file);
+        let mut other = c.clone();
+        other.add("x", 1).unwrap();
+        assert_ne!(other, c);
+        other.remove("x").unwrap();
+        assert!(other.is_dirty());
+        assert_eq!(other, c);
+
+        other.write_to_file().unwrap();
+        assert_eq!(other, c);
+        assert_ne!(other.clone().with_pretty(false), c);
 
-impl Config {
-    pub fn new() -> Self {
-        Self {
-            root: Value::Mapping(Mapping::new()),
-            file: None,
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
         }
     }
 
-    pub fn with_file(mut self, path: &str) -> Self {
-        self.file = Some(path.to_owned());
-        self
+    #[test]
+    fn contains_key() {
+        let mut c = Config::new();
+        c.add("test1", Test1 {}).unwrap();
+        c.add("database.port", 5432).unwrap();
+
+        assert!(c.contains_key("test1"));
+        assert!(c.contains_key("database"));
+        assert!(c.contains_key("database.port"));
+        assert!(!c.contains_key("test2"));
+        assert!(!c.contains_key("database.port.number"));
     }
 
-    pub fn read_from_file(&mut self) -> Result<(), Error> {
-        match File::open(self.file()?) {
-            Ok(f) => match serde_yaml::from_reader(f) {
-                Ok(m) => {
-                    self.root = m;
-                    Ok(())
-                }
-                Err(e) => Err(Error::DeserializationFailed(e.to_string())),
-            },
-            Err(e) => Err(Error::FileOpenFailed(e.to_string())),
-        }
+    #[test]
+    fn len() {
+        let mut c = Config::new();
+        assert_eq!(c.len(), 0);
+        assert!(c.is_empty());
+
+        c.add("test1", Test1 {}).unwrap();
+        c.add("db.port", 1).unwrap();
+        c.add("db.host", "localhost".to_owned()).unwrap();
+        assert_eq!(c.len(), 2);
+        assert!(!c.is_empty());
     }
 
-    pub fn write_to_file(&mut self) -> Result<(), Error> {
-        use std::fs::OpenOptions;
-        println!("{:#?}", serde_yaml::to_string(&self.root));
-        match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(self.file()?)
-        {
-            Ok(f) => {
-                serde_yaml::to_writer(f, &self.root)
-                    .map_err(|e| Error::SerializationFailed(e.to_string()))?;
-            }
-            Err(e) => {
-                return Err(Error::FileOpenFailed(e.to_string()));
-            }
-        }
-        Ok(())
+    #[test]
+    fn clear() {
+        let mut c = Config::new().with_file("config-test-c.yaml");
+        c.add("test1", Test1 {}).unwrap();
+        c.clear();
+        assert!(c.is_empty());
+        assert_eq!(c.path(), Some(Path::new("config-test-c.yaml")));
+        c.add("test1", Test1 {}).unwrap();
+        assert_eq!(c.len(), 1);
     }
 
-    pub fn add<T>(&mut self, name: &str, value: T) -> Result<(), Error>
-    where
-        T: Serialize + DeserializeOwned + 'static,
-    {
-        self.root.as_mapping_mut().unwrap().insert(
-            serde_yaml::to_value(name).unwrap(),
-            serde_yaml::to_value(value).unwrap(),
-        );
-        Ok(())
+    #[test]
+    fn keys() {
+        let mut c = Config::new();
+        assert!(c.keys().is_empty());
+
+        c.add("test1", Test1 {}).unwrap();
+        c.add("database.port", 5432).unwrap();
+        c.add("test2", Test2 { field: 0 }).unwrap();
+
+        assert_eq!(c.keys(), vec!["test1", "database", "test2"]);
     }
 
-    pub fn get<T>(&mut self, name: &str) -> Result<T, Error>
-    where
-        T: Serialize + DeserializeOwned + 'static,
-    {
-        if let Some(s) = self.root.get(name) {
-            match serde_yaml::from_value::<T>(s.to_owned()) {
-                Ok(v) => Ok(v),
-                Err(e) => Err(Error::DeserializationFailed(e.to_string())),
+    #[test]
+    fn error_display() {
+        let c = Config::from_str("db:\n  pool:\n    size: many\n").unwrap();
+        assert_eq!(
+            c.get::<std::collections::HashMap<String, u32>>("db.pool")
+                .unwrap_err()
+                .to_string(),
+            "failed to deserialize config key `db.pool`: invalid type: string \"many\", expected u32"
+        );
+        assert_eq!(
+            c.get_all::<u32>("db.pool").unwrap_err().to_string(),
+            "failed to deserialize config key `db.pool.size`: invalid type: string \"many\", expected u32"
+        );
+        assert_eq!(
+            ConfigError::KeyNotFound("db.port".to_owned()).to_string(),
+            "config key `db.port` not found"
+        );
+        assert_eq!(
+            ConfigError::FileOpenFailed {
+                path: "c.yaml".to_owned(),
+                source: "denied".to_owned()
             }
-        } else {
-            Err(Error::ConfigDoesNotEsixt)
-        }
+            .to_string(),
+            "failed to access config file `c.yaml`: denied"
+        );
+        let e: Box<dyn std::error::Error> = Box::new(ConfigError::FileNotSet);
+        assert_eq!(e.to_string(), "config file path is not set");
     }
 
-    fn file(&self) -> Result<&str, Error> {
-        self.file.deref().ok_or(Error::FileDoesNotSet)
-    }
-}
+    #[test]
+    fn from_str_to_string() {
+        let c = Config::from_str("test2:\n  field: 123\ndb:\n  port: 5432\n").unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 123);
+        assert_eq!(c.get::<u16>("db.port").unwrap(), 5432);
+        assert_eq!(
+            c.to_string().unwrap(),
+            "test2:\n  field: 123\ndb:\n  port: 5432\n"
+        );
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
-    use std::io::{Read, Write};
+        match Config::from_str("a: [") {
+            Err(ConfigError::DeserializationFailed { key, message }) => {
+                assert_eq!(key, "");
+                assert!(message.contains("line 2 column 1"), "{}", message);
+            }
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
 
-    use super::{Config, Error as ConfigError};
+    #[test]
+    fn read_from_write_to() {
+        let mut c = Config::new();
+        c.read_from("test2:\n  field: 7\n".as_bytes()).unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 7);
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Test1;
+        let mut out = Vec::new();
+        c.write_to(&mut out).unwrap();
+        assert_eq!(out, b"test2:\n  field: 7\n");
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Test2 {
-        field: usize,
+        let mut out = Vec::new();
+        let c = c.with_format(Format::Json);
+        c.write_to(&mut out).unwrap();
+        assert_eq!(out, b"{\n  \"test2\": {\n    \"field\": 7\n  }\n}\n");
     }
 
     #[test]
-    fn create_new() {
-        let mut c = Config::new();
+    fn root_not_mapping() {
+        assert_eq!(
+            Config::from_str("- 1\n- 2").err(),
+            Some(ConfigError::RootNotMapping)
+        );
+        assert_eq!(
+            Config::from_str("42").err(),
+            Some(ConfigError::RootNotMapping)
+        );
+        assert!(Config::from_str("").unwrap().keys().is_empty());
 
+        let mut c = Config::new();
         c.add("test1", Test1 {}).unwrap();
-        c.add("test2", Test2 { field: 0 }).unwrap();
+        assert_eq!(
+            c.read_from("[1, 2]".as_bytes()).unwrap_err(),
+            ConfigError::RootNotMapping
+        );
+        assert!(c.contains_key("test1"));
+    }
 
-        let temp: Test2 = c.get("test2").unwrap();
-        println!("{:#?}", temp);
+    #[test]
+    fn path() {
+        assert_eq!(Config::new().path(), None);
+        assert_eq!(
+            Config::new().with_file("config.yaml").path(),
+            Some(Path::new("config.yaml"))
+        );
+    }
 
-        let temp: Test1 = c.get("test1").unwrap();
-        println!("{:#?}", temp);
+    #[test]
+    fn non_utf8_path() {
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+            use std::path::PathBuf;
+
+            let test_file = PathBuf::from(OsStr::from_bytes(b"config-test-nup-\xff.yaml"));
+            let mut c = Config::new().with_file(&test_file);
+            c.add("test1", Test1 {}).unwrap();
+            c.write_to_file().unwrap();
+            c.read_from_file().unwrap();
+            assert!(c.contains_key("test1"));
+            if let Err(e) = fs::remove_file(&test_file) {
+                println!("{:#?}", e);
+            }
+        }
     }
 
     #[test]
     fn read_from_unset_file() {
         let mut c = Config::new();
-        assert_eq!(c.read_from_file().unwrap_err(), ConfigError::FileDoesNotSet);
+        assert_eq!(c.read_from_file().unwrap_err(), ConfigError::FileNotSet);
     }
 
     #[test]
@@ -146,7 +3746,17 @@ mod tests {
             println!("{:#?}", e);
         }
         let mut c = Config::new().with_file(test_file);
-        assert!(c.read_from_file().is_err());
+        match c.read_from_file().unwrap_err() {
+            ConfigError::FileNotFound(path) => assert_eq!(path, test_file),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Anything else that stops the file from being read is not "not found".
+        let mut c = Config::new().with_file("src");
+        assert!(matches!(
+            c.read_from_file(),
+            Err(ConfigError::FileOpenFailed { .. })
+        ));
         if let Err(e) = fs::remove_file(test_file) {
             println!("{:#?}", e);
         }
@@ -219,4 +3829,450 @@ mod tests {
             println!("{:#?}", e);
         }
     }
+
+    #[test]
+    fn json_round_trip() {
+        let test_file = "config-test-jrt.json";
+        let mut c = Config::new().with_file(test_file);
+        c.add("test2", Test2 { field: 42 }).unwrap();
+        c.add("database.host", "localhost".to_owned()).unwrap();
+        c.write_to_file().unwrap();
+
+        let content = fs::read_to_string(test_file).unwrap();
+        assert!(content.starts_with('{'));
+
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 42);
+        assert_eq!(c.get::<String>("database.host").unwrap(), "localhost");
+
+        let mut c = Config::new().with_file(test_file).with_format(Format::Yaml);
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 42);
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn json_pretty() {
+        let mut c = Config::new().with_format(Format::Json);
+        c.add("a.b", 1).unwrap();
+
+        let mut out = Vec::new();
+        c.write_to(&mut out).unwrap();
+        assert_eq!(out, b"{\n  \"a\": {\n    \"b\": 1\n  }\n}\n");
+
+        let c = c.with_pretty(false);
+        let mut out = Vec::new();
+        c.write_to(&mut out).unwrap();
+        assert_eq!(out, b"{\"a\":{\"b\":1}}\n");
+    }
+
+    #[test]
+    fn sorted_keys() {
+        let mut c = Config::from_str("b: 1\na:\n  d: [{y: 1, x: 2}]\n  c: 3\n")
+            .unwrap()
+            .with_sorted_keys(true);
+        c.add("0", 0).unwrap();
+
+        let mut out = Vec::new();
+        c.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "'0': 0\na:\n  c: 3\n  d:\n  - x: 2\n    y: 1\nb: 1\n"
+        );
+        assert_eq!(c.keys(), vec!["b", "a", "0"]);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Connection {
+            host: String,
+            port: u16,
+        }
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Database {
+            name: String,
+            replicas: Vec<Connection>,
+            primary: Connection,
+            timeout: f64,
+        }
+
+        let db = Database {
+            name: "main".to_owned(),
+            replicas: vec![Connection {
+                host: "replica".to_owned(),
+                port: 5433,
+            }],
+            primary: Connection {
+                host: "primary".to_owned(),
+                port: 5432,
+            },
+            timeout: 1.5,
+        };
+        let test_file = "config-test-trt.toml";
+        let mut c = Config::new().with_file(test_file);
+        c.add("database", db).unwrap();
+        c.write_to_file().unwrap();
+
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+        let db: Database = c.get("database").unwrap();
+        assert_eq!(db.primary.port, 5432);
+        assert_eq!(db.replicas[0].host, "replica");
+        assert_eq!(db.timeout, 1.5);
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn toml_root_not_table() {
+        let test_file = "config-test-trnt.toml";
+        let mut c = Config::new().with_file(test_file);
+        c.root = serde_yaml::Value::from(1);
+        match c.write_to_file() {
+            Err(ConfigError::SerializationFailed(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn overwrite_with_shorter_content() {
+        let test_file = "config-test-owsc.yaml";
+        let mut c = Config::new().with_file(test_file);
+        for i in 0..100 {
+            c.add(&format!("key{}", i), i).unwrap();
+        }
+        c.write_to_file().unwrap();
+
+        let mut c = Config::new().with_file(test_file);
+        c.add("key0", 0).unwrap();
+        c.write_to_file().unwrap();
+
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+        assert_eq!(c.keys(), vec!["key0"]);
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn atomic_write() {
+        let test_file = "config-test-aw.yaml";
+        let mut c = Config::new().with_file(test_file);
+        c.add("test2", Test2 { field: 1 }).unwrap();
+        c.write_to_file().unwrap();
+        assert!(!std::path::Path::new("config-test-aw.yaml.tmp").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(test_file, fs::Permissions::from_mode(0o600)).unwrap();
+            c.write_to_file().unwrap();
+            let mode = fs::metadata(test_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            use std::os::unix::fs::{chown, MetadataExt};
+            if chown(test_file, Some(4321), Some(4321)).is_ok() {
+                c.write_to_file().unwrap();
+                let meta = fs::metadata(test_file).unwrap();
+                assert_eq!((meta.uid(), meta.gid()), (4321, 4321));
+            }
+        }
+
+        let mut c = Config::new().with_file(test_file).with_atomic_write(false);
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 1);
+        c.add("test2", Test2 { field: 2 }).unwrap();
+        c.write_to_file().unwrap();
+        c.read_from_file().unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 2);
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn reload() {
+        let test_file = "config-test-rl.yaml";
+        fs::write(test_file, "test2:\n  field: 1\n").unwrap();
+        let mut c = Config::new().with_file(test_file);
+        c.read_from_file().unwrap();
+
+        fs::write(test_file, "test2:\n  field: 2\n").unwrap();
+        c.reload().unwrap();
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 2);
+
+        fs::write(test_file, "test2: [").unwrap();
+        assert!(c.reload().is_err());
+        assert_eq!(c.get::<Test2>("test2").unwrap().field, 2);
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn validate_schema() {
+        let schema: serde_yaml::Value = serde_yaml::from_str(
+            r#"{
+                "type": "object",
+                "required": ["name", "port"],
+                "properties": {
+                    "name": {"type": "string", "minLength": 1},
+                    "port": {"type": "integer", "minimum": 1, "maximum": 65535},
+                    "mode": {"enum": ["dev", "prod"]},
+                    "hosts": {"type": "array", "items": {"type": "string"}}
+                },
+                "additionalProperties": false
+            }"#,
+        )
+        .unwrap();
+
+        let c = Config::from_str("name: app\nport: 80\nhosts: [a, b]\n").unwrap();
+        assert_eq!(c.validate_schema(&schema), Ok(()));
+
+        let c =
+            Config::from_str("name: ''\nport: 70000\nmode: test\nhosts: [a, 1]\nx: 1\n").unwrap();
+        let errors: Vec<String> = c
+            .validate_schema(&schema)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "config value at `name` must have at least 1 characters",
+                "config value at `port` must be at most 65535",
+                "config value at `mode` must be one of dev, prod",
+                "config value at `hosts.1` must be of type string, found integer",
+                "config value at `x` is not allowed",
+            ]
+        );
+
+        let c = Config::from_str("name: app\n").unwrap();
+        assert_eq!(
+            c.validate_schema(&schema).unwrap_err()[0].to_string(),
+            "config is missing required key `port`"
+        );
+
+        let schema: serde_yaml::Value = serde_yaml::from_str(
+            r##"{
+                "title": "App",
+                "$ref": "#/$defs/app",
+                "properties": {
+                    "name": {"type": "string", "pattern": "^a"},
+                    "hosts": {"items": {"format": "hostname"}}
+                },
+                "anyOf": [{"required": ["name"]}, {"if": true}]
+            }"##,
+        )
+        .unwrap();
+        let errors: Vec<String> = c
+            .validate_schema(&schema)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "config schema keyword `$ref` is not supported",
+                "config schema keyword `pattern` at `properties.name` is not supported",
+                "config schema keyword `format` at `properties.hosts.items` is not supported",
+                "config schema keyword `if` at `anyOf.1` is not supported",
+            ]
+        );
+
+        let schema: serde_yaml::Value = serde_yaml::from_str(
+            r#"{"properties": {"pair": {"items": [{"type": "string"}]}, "n": 3}}"#,
+        )
+        .unwrap();
+        let errors: Vec<String> = c
+            .validate_schema(&schema)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "config schema keyword `items` at `properties.pair` as an array is not supported",
+                "config schema at `properties.n` is not an object or boolean",
+            ]
+        );
+
+        let schema: serde_yaml::Value = serde_yaml::from_str(
+            r#"{"properties": {"ratio": {"enum": [0.5, 1.0]}, "port": {"const": 80}}}"#,
+        )
+        .unwrap();
+        let c = Config::from_str("ratio: 1\nport: 80.0\n").unwrap();
+        assert_eq!(c.validate_schema(&schema), Ok(()));
+        let c = Config::from_str("ratio: 2\nport: 81\n").unwrap();
+        assert_eq!(c.validate_schema(&schema).unwrap_err().len(), 2);
+    }
+
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake};
+        use std::thread::{self, Thread};
+
+        struct Unpark(Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn file_io_async() {
+        let test_file = "config-test-async.yaml";
+        let mut c = Config::new().with_file(test_file).with_value("a", 1);
+        block_on(c.write_to_file_async()).unwrap();
+        assert!(!c.is_dirty());
+
+        let mut c = Config::new().with_file(test_file);
+        block_on(c.read_from_file_async()).unwrap();
+        assert_eq!(c.get::<i32>("a"), Ok(1));
+
+        let mut c = Config::new().with_file("config-test-async-missing.yaml");
+        assert!(matches!(
+            block_on(c.read_from_file_async()),
+            Err(ConfigError::FileNotFound(_))
+        ));
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn trace_events() {
+        use super::{set_trace_hook, TraceEvent};
+        use std::sync::Mutex;
+
+        static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn record(event: &TraceEvent) {
+            let event = format!("{:?}", event);
+            if event.contains("trace-test") {
+                EVENTS.lock().unwrap().push(event);
+            }
+        }
+
+        set_trace_hook(Some(record));
+        let test_file = "config-test-trace-test.yaml";
+        let mut c = Config::new().with_file(test_file);
+        c.add("trace-test", "secret".to_owned()).unwrap();
+        c.write_to_file().unwrap();
+        c.reload().unwrap();
+        c.remove("trace-test").unwrap();
+        let c2 = Config::from_str("Trace-Test: 1\nTRACE-TEST: 2\n").unwrap();
+        let c2 = c2.with_case_insensitive(true);
+        assert_eq!(c2.get::<usize>("trace-test"), Ok(1));
+        set_trace_hook(None);
+        c.add("trace-test", 1).unwrap();
+
+        assert_eq!(
+            *EVENTS.lock().unwrap(),
+            vec![
+                "Add { key: \"trace-test\" }",
+                "Write { path: \"config-test-trace-test.yaml\" }",
+                "Reload { path: \"config-test-trace-test.yaml\" }",
+                "Read { path: \"config-test-trace-test.yaml\" }",
+                "Remove { key: \"trace-test\" }",
+                "AmbiguousKey { key: \"trace-test\", used: \"Trace-Test\" }",
+            ]
+        );
+
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let test_file = "config-test-w.yaml";
+        fs::write(test_file, "test2:\n  field: 1\n").unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handle = Config::new()
+            .with_file(test_file)
+            .watch_every(Duration::from_millis(20), move |c| {
+                let _ = tx.send(c.get::<Test2>("test2").map(|t| t.field));
+            })
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+        fs::write(test_file, "test2: [").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        fs::write(test_file, "test2:\n  field: 22\n").unwrap();
+        let field = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(field, Ok(22));
+
+        drop(handle);
+        assert!(rx.recv().is_err());
+        if let Err(e) = fs::remove_file(test_file) {
+            println!("{:#?}", e);
+        }
+    }
+
+    #[test]
+    fn default_path_and_parent_dirs() {
+        let c = Config::new().with_default_path("tuneup-test");
+        if let Some(path) = c.path() {
+            assert!(path.ends_with("tuneup-test/config.yaml"));
+        }
+
+        let test_dir = "config-test-dpapd";
+        let test_file = "config-test-dpapd/nested/config.yaml";
+        if let Err(e) = fs::remove_dir_all(test_dir) {
+            println!("{:#?}", e);
+        }
+        let mut c = Config::new().with_file(test_file);
+        c.add("test2", Test2 { field: 1 }).unwrap();
+        c.ensure_parent_dirs().unwrap();
+        c.write_to_file().unwrap();
+        assert!(fs::metadata(test_file).is_ok());
+        fs::remove_dir_all(test_dir).unwrap();
+
+        let mut c = c.with_create_dirs(false);
+        match c.write_to_file() {
+            Err(ConfigError::FileOpenFailed { .. }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let mut c = c.with_create_dirs(true);
+        c.write_to_file().unwrap();
+        assert!(fs::metadata(test_file).is_ok());
+        if let Err(e) = fs::remove_dir_all(test_dir) {
+            println!("{:#?}", e);
+        }
+    }
 }
@@ -0,0 +1,34 @@
+use serde_yaml::Value;
+
+/// How `Config::merge_with` combines sequences present on both sides.
+///
+/// Mappings are always merged key by key. Any other pair of values,
+/// including a type mismatch such as a scalar in the base and a mapping in
+/// the overlay, is resolved by taking the overlay value as is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeStrategy {
+    /// The overlay sequence replaces the base sequence.
+    Overwrite,
+    /// Overlay elements are appended to the base sequence.
+    Append,
+}
+
+/// Deep-merges `overlay` into `base`.
+pub(crate) fn merge_values(base: &mut Value, overlay: &Value, strategy: MergeStrategy) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(k) {
+                    Some(existing) => merge_values(existing, v, strategy),
+                    None => {
+                        base.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(overlay)) if strategy == MergeStrategy::Append => {
+            base.extend(overlay.iter().cloned());
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
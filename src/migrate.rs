@@ -0,0 +1,41 @@
+use crate::{Config, Error};
+
+/// A step from one config version to the next: `(from, to, migration)`.
+pub type Migration = (u32, u32, fn(&mut Config) -> Result<(), Error>);
+
+impl Config {
+    /// Sets the key holding the config version used by `migrate`,
+    /// `version` by default.
+    pub fn with_version_key(mut self, key: &str) -> Self {
+        self.version_key = key.to_owned();
+        self
+    }
+
+    /// Upgrades the config to the newest version known to `migrations`.
+    ///
+    /// A config without a version is at version 0. Starting there, the
+    /// migration whose `from` matches the current version runs and the
+    /// version key is set to its `to`, until no migration applies. Call this
+    /// after loading and `write_to_file` afterwards to persist the upgrade.
+    /// A config newer than any `to` fails with `ConfigVersionTooNew` before
+    /// anything is changed; if a migration fails, the steps before it stay
+    /// applied.
+    pub fn migrate(&mut self, migrations: &[Migration]) -> Result<(), Error> {
+        let key = self.version_key.clone();
+        let mut version = self.get_opt::<u32>(&key)?.unwrap_or(0);
+        let supported = migrations.iter().map(|m| m.1).max().unwrap_or(0);
+        if version > supported {
+            return Err(Error::ConfigVersionTooNew {
+                found: version,
+                supported,
+            });
+        }
+        while let Some((_, to, migration)) = migrations.iter().find(|m| m.0 == version && m.1 > m.0)
+        {
+            migration(self)?;
+            version = *to;
+            self.add(&key, version)?;
+        }
+        Ok(())
+    }
+}
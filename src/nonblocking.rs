@@ -0,0 +1,90 @@
+use crate::{Config, Error};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+impl Config {
+    /// Like `read_from_file`, but the file is read and parsed on a helper
+    /// thread, so awaiting it doesn't block the executor. The future doesn't
+    /// depend on a particular runtime. The config is only updated once the
+    /// read succeeds; dropping the future before that leaves it untouched.
+    pub async fn read_from_file_async(&mut self) -> Result<(), Error> {
+        let mut reader = self.clone();
+        reader.autosave = false;
+        let mut reader = blocking(move || reader.read_from_file().map(|_| reader)).await?;
+        self.root = std::mem::take(&mut reader.root);
+        self.docs = std::mem::take(&mut reader.docs);
+        self.file_stamp = reader.file_stamp;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Like `write_to_file`, but the config is serialized and written on a
+    /// helper thread. A snapshot is taken when the future is first polled,
+    /// so later changes aren't included.
+    pub async fn write_to_file_async(&mut self) -> Result<(), Error> {
+        let mut writer = self.clone();
+        writer.autosave = false;
+        let stamp = blocking(move || {
+            writer.write_to_file()?;
+            Ok::<_, Error>(writer.file_stamp)
+        })
+        .await?;
+        self.dirty = false;
+        self.file_stamp = stamp;
+        Ok(())
+    }
+}
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Result of `f`, run on its own thread.
+struct Blocking<T>(Arc<Mutex<Shared<T>>>);
+
+fn blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let done = shared.clone();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let mut done = lock(&done);
+        done.result = Some(result);
+        if let Some(waker) = done.waker.take() {
+            waker.wake();
+        }
+    });
+    Blocking(shared)
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = lock(&self.0);
+        match shared.result.take() {
+            Some(Ok(v)) => Poll::Ready(v),
+            // The helper thread only panics on a bug; pass it on to the task.
+            Some(Err(e)) => panic::resume_unwind(e),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,279 @@
+//! Traversal of dotted key paths such as `database.connection.port`.
+//!
+//! Segments index mappings by key and sequences by position, so
+//! `servers.0.host` reads `host` from the first element of `servers`. Every
+//! function takes the separator, which a `Config` can change from the
+//! default `.`.
+//!
+//! Tagged values such as `db: !env {host: x}` are looked through, so
+//! `db.host` reaches into them and changes below keep the tag.
+
+use crate::Error;
+use serde_yaml::{Mapping, Value};
+use std::mem;
+
+/// Value at `path`, or `None` if some segment is missing.
+pub(crate) fn lookup<'a>(
+    root: &'a Value,
+    path: &str,
+    sep: char,
+) -> Result<Option<&'a Value>, Error> {
+    lookup_with(root, path, sep, |m, key| m.get(key))
+}
+
+/// Like `lookup`, but matches mapping keys ignoring ASCII case. If several
+/// keys match, the first one wins and `TraceEvent::AmbiguousKey` is
+/// reported.
+pub(crate) fn lookup_ignore_case<'a>(
+    root: &'a Value,
+    path: &str,
+    sep: char,
+) -> Result<Option<&'a Value>, Error> {
+    lookup_with(root, path, sep, |m, key| {
+        find_ignore_case(m, key).map(|(_, v)| v)
+    })
+}
+
+/// `path` with each segment that `lookup_ignore_case` would match spelled
+/// as it is in the tree. Resolution stops at the first segment that isn't
+/// found; it and the ones after are kept as given, so a write creates them
+/// and a failing operation reports its own error.
+pub(crate) fn resolve_ignore_case(root: &Value, path: &str, sep: char) -> String {
+    let segments = split(path, sep);
+    let mut resolved = Vec::with_capacity(segments.len());
+    let mut node = root;
+    for i in 0..segments.len() {
+        let child = match untag(node) {
+            Value::Mapping(m) => find_ignore_case(m, segments[i]),
+            Value::Sequence(seq) => index(seq.len(), &segments, i, sep)
+                .ok()
+                .map(|idx| (segments[i], &seq[idx])),
+            _ => None,
+        };
+        match child {
+            Some((key, v)) => {
+                resolved.push(key);
+                node = v;
+            }
+            None => {
+                resolved.extend_from_slice(&segments[i..]);
+                break;
+            }
+        }
+    }
+    join(&resolved, sep)
+}
+
+/// First entry of `m` whose key matches `key` ignoring ASCII case.
+fn find_ignore_case<'a>(m: &'a Mapping, key: &str) -> Option<(&'a str, &'a Value)> {
+    let mut matches = m.iter().filter_map(|(k, v)| match k.as_str() {
+        Some(k) if k.eq_ignore_ascii_case(key) => Some((k, v)),
+        _ => None,
+    });
+    let first = matches.next()?;
+    if matches.next().is_some() {
+        trace_event!(AmbiguousKey { key, used: first.0 });
+    }
+    Some(first)
+}
+
+fn lookup_with<'a, F>(
+    root: &'a Value,
+    path: &str,
+    sep: char,
+    find: F,
+) -> Result<Option<&'a Value>, Error>
+where
+    F: Fn(&'a Mapping, &str) -> Option<&'a Value>,
+{
+    let segments = split(path, sep);
+    let mut node = root;
+    for i in 0..segments.len() {
+        let child = match untag(node) {
+            Value::Mapping(m) => find(m, segments[i]),
+            Value::Sequence(seq) => Some(&seq[index(seq.len(), &segments, i, sep)?]),
+            _ => return Err(not_a_mapping(&segments[..i], sep)),
+        };
+        match child {
+            Some(v) => node = v,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(node))
+}
+
+pub(crate) fn lookup_mut<'a>(
+    root: &'a mut Value,
+    path: &str,
+    sep: char,
+) -> Result<Option<&'a mut Value>, Error> {
+    lookup_segments_mut(root, &split(path, sep), sep)
+}
+
+fn lookup_segments_mut<'a>(
+    root: &'a mut Value,
+    segments: &[&str],
+    sep: char,
+) -> Result<Option<&'a mut Value>, Error> {
+    let mut node = root;
+    for i in 0..segments.len() {
+        let child = match untag_mut(node) {
+            Value::Mapping(m) => m.get_mut(segments[i]),
+            Value::Sequence(seq) => {
+                let idx = index(seq.len(), segments, i, sep)?;
+                Some(&mut seq[idx])
+            }
+            _ => return Err(not_a_mapping(&segments[..i], sep)),
+        };
+        match child {
+            Some(v) => node = v,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(node))
+}
+
+/// Stores `value` at `path`, creating missing intermediate mappings, and
+/// returns the value it replaced. Sequence elements can be replaced but
+/// not created, see `push`.
+pub(crate) fn insert(
+    root: &mut Value,
+    path: &str,
+    value: Value,
+    sep: char,
+) -> Result<Option<Value>, Error> {
+    let segments = split(path, sep);
+    let mut node = root;
+    for i in 0..segments.len() - 1 {
+        node = match untag_mut(node) {
+            Value::Mapping(m) => m
+                .entry(Value::from(segments[i]))
+                .or_insert_with(|| Value::Mapping(Mapping::new())),
+            Value::Sequence(seq) => {
+                let idx = index(seq.len(), &segments, i, sep)?;
+                &mut seq[idx]
+            }
+            _ => return Err(not_a_mapping(&segments[..i], sep)),
+        };
+    }
+    let last = segments.len() - 1;
+    match untag_mut(node) {
+        Value::Mapping(m) => Ok(m.insert(Value::from(segments[last]), value)),
+        Value::Sequence(seq) => {
+            let idx = index(seq.len(), &segments, last, sep)?;
+            Ok(Some(mem::replace(&mut seq[idx], value)))
+        }
+        _ => Err(not_a_mapping(&segments[..last], sep)),
+    }
+}
+
+/// Removes and returns the value at `path`, or `None` if it isn't set.
+/// Removing a sequence element shifts the following ones down.
+pub(crate) fn remove(root: &mut Value, path: &str, sep: char) -> Result<Option<Value>, Error> {
+    let segments = split(path, sep);
+    let (last, parents) = segments.split_last().unwrap();
+    let parent = match lookup_segments_mut(root, parents, sep)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    match untag_mut(parent) {
+        Value::Mapping(m) => Ok(m.shift_remove(*last)),
+        Value::Sequence(seq) => match last.parse::<usize>() {
+            Ok(i) if i < seq.len() => Ok(Some(seq.remove(i))),
+            Ok(_) => Ok(None),
+            Err(_) => Err(not_a_mapping(parents, sep)),
+        },
+        _ => Err(not_a_mapping(parents, sep)),
+    }
+}
+
+/// Appends `value` to the sequence at `path`, creating the sequence if
+/// nothing is set there yet.
+pub(crate) fn push(root: &mut Value, path: &str, value: Value, sep: char) -> Result<(), Error> {
+    match lookup_mut(root, path, sep)?.map(untag_mut) {
+        Some(Value::Sequence(seq)) => seq.push(value),
+        Some(_) => return Err(Error::NotASequence(path.to_owned())),
+        None => {
+            insert(root, path, Value::Sequence(vec![value]), sep)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the value at `from` to `to`, which must not be set yet. Renaming
+/// a key within the same mapping keeps its position there.
+pub(crate) fn rename(root: &mut Value, from: &str, to: &str, sep: char) -> Result<(), Error> {
+    if lookup(root, to, sep)?.is_some() {
+        return Err(Error::KeyExists(to.to_owned()));
+    }
+    let (from_last, from_parents) = split_last(from, sep);
+    let (to_last, to_parents) = split_last(to, sep);
+    if from_parents == to_parents {
+        let parent = lookup_segments_mut(root, &from_parents, sep)?.map(untag_mut);
+        if let Some(Value::Mapping(m)) = parent {
+            if !m.contains_key(from_last) {
+                return Err(Error::KeyNotFound(from.to_owned()));
+            }
+            *m = mem::take(m)
+                .into_iter()
+                .map(|(k, v)| {
+                    if k == from_last {
+                        (to_last.into(), v)
+                    } else {
+                        (k, v)
+                    }
+                })
+                .collect();
+            return Ok(());
+        }
+    }
+    let value = remove(root, from, sep)?.ok_or_else(|| Error::KeyNotFound(from.to_owned()))?;
+    insert(root, to, value, sep).map(|_| ())
+}
+
+/// The value inside any tags on `v`.
+fn untag(mut v: &Value) -> &Value {
+    while let Value::Tagged(t) = v {
+        v = &t.value;
+    }
+    v
+}
+
+fn untag_mut(mut v: &mut Value) -> &mut Value {
+    while let Value::Tagged(t) = v {
+        v = &mut t.value;
+    }
+    v
+}
+
+fn split_last(path: &str, sep: char) -> (&str, Vec<&str>) {
+    let mut segments = split(path, sep);
+    let last = segments.pop().unwrap();
+    (last, segments)
+}
+
+fn split(path: &str, sep: char) -> Vec<&str> {
+    path.split(sep).collect()
+}
+
+pub(crate) fn join(segments: &[&str], sep: char) -> String {
+    segments.join(&sep.to_string())
+}
+
+/// Parses `segments[i]` as an index into a sequence of length `len`.
+fn index(len: usize, segments: &[&str], i: usize, sep: char) -> Result<usize, Error> {
+    match segments[i].parse::<usize>() {
+        Ok(idx) if idx < len => Ok(idx),
+        Ok(_) => Err(Error::IndexOutOfBounds(join(&segments[..=i], sep))),
+        Err(_) => Err(not_a_mapping(&segments[..i], sep)),
+    }
+}
+
+/// Error for a non-mapping value reached after traversing `segments`.
+fn not_a_mapping(segments: &[&str], sep: char) -> Error {
+    if segments.is_empty() {
+        Error::RootNotMapping
+    } else {
+        Error::NotAMapping(join(segments, sep))
+    }
+}
@@ -0,0 +1,412 @@
+//! Validation against a JSON Schema, see `Config::validate_schema`.
+
+use crate::{path, Config};
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+
+/// A value that doesn't match the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// Dotted path of the value, empty for the root.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "config {}", self.message)
+        } else {
+            write!(f, "config value at `{}` {}", self.path, self.message)
+        }
+    }
+}
+
+/// Path segments of a failing value and the message.
+type Failure = (Vec<String>, String);
+
+/// Keywords that are checked.
+const SUPPORTED: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minLength",
+    "maxLength",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+];
+
+/// Keywords that only annotate a schema and never affect validation.
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+impl Config {
+    /// Checks the config against the JSON Schema `schema`, returning every
+    /// violation found. JSON is valid YAML, so a schema file can be loaded
+    /// with `serde_yaml::from_str`.
+    ///
+    /// Supported keywords are `type`, `enum`, `const`, `properties`,
+    /// `required`, `additionalProperties`, `items`, `minItems`, `maxItems`,
+    /// `minLength`, `maxLength`, `minimum`, `maximum`, `exclusiveMinimum`,
+    /// `exclusiveMaximum`, `allOf`, `anyOf`, `oneOf` and `not`, plus
+    /// annotations such as `title` and `description`. Numbers in `enum` and
+    /// `const` match by value, so `1` matches `1.0`. A schema using any
+    /// other keyword, e.g. `$ref` or `pattern`, or the array form of
+    /// `items`, isn't checked at all:
+    /// instead every such keyword is returned as an error with an empty
+    /// `path`, so a schema never passes just because part of it was
+    /// skipped.
+    pub fn validate_schema(&self, schema: &Value) -> Result<(), Vec<ValidationError>> {
+        let mut unsupported = Vec::new();
+        find_unsupported(schema, &mut Vec::new(), &mut unsupported);
+        if !unsupported.is_empty() {
+            return Err(unsupported);
+        }
+        let mut failures = Vec::new();
+        validate(&self.root, schema, &mut Vec::new(), &mut failures);
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let errors = failures.into_iter().map(|(at, message)| {
+            let segments: Vec<&str> = at.iter().map(String::as_str).collect();
+            ValidationError {
+                path: path::join(&segments, self.separator),
+                message,
+            }
+        });
+        Err(errors.collect())
+    }
+}
+
+/// Collects the keywords of `schema` and its subschemas that aren't
+/// checked. `at` is the location in the schema.
+fn find_unsupported(schema: &Value, at: &mut Vec<String>, errors: &mut Vec<ValidationError>) {
+    let schema = match schema {
+        Value::Mapping(m) => m,
+        Value::Bool(_) => return,
+        _ => {
+            let message = format!("schema{} is not an object or boolean", location(at));
+            return unsupported(errors, message);
+        }
+    };
+    for (k, sub) in schema {
+        let keyword = match k.as_str() {
+            Some(k) => k,
+            None => continue,
+        };
+        at.push(keyword.to_owned());
+        match (keyword, sub) {
+            ("properties", Value::Mapping(props)) => {
+                for (name, prop) in props {
+                    at.push(name.as_str().map_or_else(|| inline(name), str::to_owned));
+                    find_unsupported(prop, at, errors);
+                    at.pop();
+                }
+            }
+            ("allOf", Value::Sequence(subs))
+            | ("anyOf", Value::Sequence(subs))
+            | ("oneOf", Value::Sequence(subs)) => {
+                for (i, sub) in subs.iter().enumerate() {
+                    at.push(i.to_string());
+                    find_unsupported(sub, at, errors);
+                    at.pop();
+                }
+            }
+            ("items", Value::Sequence(_)) => {
+                let message = format!(
+                    "schema keyword `items`{} as an array is not supported",
+                    location(&at[..at.len() - 1])
+                );
+                unsupported(errors, message);
+            }
+            ("additionalProperties", _) | ("items", _) | ("not", _) => {
+                find_unsupported(sub, at, errors)
+            }
+            (k, _) if SUPPORTED.contains(&k) || ANNOTATIONS.contains(&k) => {}
+            (k, _) => {
+                let message = format!(
+                    "schema keyword `{}`{} is not supported",
+                    k,
+                    location(&at[..at.len() - 1])
+                );
+                unsupported(errors, message);
+            }
+        }
+        at.pop();
+    }
+}
+
+/// ` at `path`` for a location in the schema, empty for the root.
+fn location(at: &[String]) -> String {
+    if at.is_empty() {
+        String::new()
+    } else {
+        format!(" at `{}`", at.join("."))
+    }
+}
+
+fn unsupported(errors: &mut Vec<ValidationError>, message: String) {
+    errors.push(ValidationError {
+        path: String::new(),
+        message,
+    });
+}
+
+fn validate(v: &Value, schema: &Value, at: &mut Vec<String>, errors: &mut Vec<Failure>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => return fail(at, errors, "is not allowed".to_owned()),
+        Value::Mapping(m) => m,
+        _ => return,
+    };
+    let v = match v {
+        Value::Tagged(t) => &t.value,
+        v => v,
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(t) => vec![t],
+            Value::Sequence(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(v, t)) {
+            let message = format!(
+                "must be of type {}, found {}",
+                types.join(" or "),
+                type_of(v)
+            );
+            return fail(at, errors, message);
+        }
+    }
+    if let Some(Value::Sequence(options)) = schema.get("enum") {
+        if !options.iter().any(|option| same(v, option)) {
+            fail(at, errors, format!("must be one of {}", list(options)));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if !same(v, expected) {
+            fail(at, errors, format!("must be {}", inline(expected)));
+        }
+    }
+
+    match v {
+        Value::Mapping(m) => validate_object(m, schema, at, errors),
+        Value::Sequence(seq) => {
+            check_len(
+                seq.len(),
+                schema,
+                "minItems",
+                "maxItems",
+                "items",
+                at,
+                errors,
+            );
+            if let Some(items) = schema.get("items") {
+                for (i, item) in seq.iter().enumerate() {
+                    at.push(i.to_string());
+                    validate(item, items, at, errors);
+                    at.pop();
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count();
+            check_len(
+                len,
+                schema,
+                "minLength",
+                "maxLength",
+                "characters",
+                at,
+                errors,
+            );
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            for (keyword, relation) in &[
+                ("minimum", "at least"),
+                ("maximum", "at most"),
+                ("exclusiveMinimum", "greater than"),
+                ("exclusiveMaximum", "less than"),
+            ] {
+                let limit = match schema.get(*keyword).and_then(Value::as_f64) {
+                    Some(limit) => limit,
+                    None => continue,
+                };
+                let ok = match *keyword {
+                    "minimum" => n >= limit,
+                    "maximum" => n <= limit,
+                    "exclusiveMinimum" => n > limit,
+                    _ => n < limit,
+                };
+                if !ok {
+                    let message = format!("must be {} {}", relation, inline(&schema[*keyword]));
+                    fail(at, errors, message);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Sequence(all)) = schema.get("allOf") {
+        for s in all {
+            validate(v, s, at, errors);
+        }
+    }
+    if let Some(Value::Sequence(any)) = schema.get("anyOf") {
+        if !any.iter().any(|s| is_valid(v, s)) {
+            fail(
+                at,
+                errors,
+                "must match at least one schema in anyOf".to_owned(),
+            );
+        }
+    }
+    if let Some(Value::Sequence(one)) = schema.get("oneOf") {
+        let matched = one.iter().filter(|s| is_valid(v, s)).count();
+        if matched != 1 {
+            let message = format!(
+                "must match exactly one schema in oneOf, matched {}",
+                matched
+            );
+            fail(at, errors, message);
+        }
+    }
+    if let Some(not) = schema.get("not") {
+        if is_valid(v, not) {
+            fail(at, errors, "must not match the schema in not".to_owned());
+        }
+    }
+}
+
+fn validate_object(m: &Mapping, schema: &Mapping, at: &mut Vec<String>, errors: &mut Vec<Failure>) {
+    if let Some(Value::Sequence(required)) = schema.get("required") {
+        for key in required.iter().filter_map(Value::as_str) {
+            if !m.contains_key(key) {
+                fail(at, errors, format!("is missing required key `{}`", key));
+            }
+        }
+    }
+    let properties = schema.get("properties").and_then(Value::as_mapping);
+    let additional = schema.get("additionalProperties");
+    for (k, item) in m {
+        let key = match k {
+            Value::String(s) => s.clone(),
+            k => inline(k),
+        };
+        let item_schema = properties.and_then(|p| p.get(k)).or(additional);
+        if let Some(item_schema) = item_schema {
+            at.push(key);
+            validate(item, item_schema, at, errors);
+            at.pop();
+        }
+    }
+}
+
+fn check_len(
+    len: usize,
+    schema: &Mapping,
+    min: &str,
+    max: &str,
+    unit: &str,
+    at: &[String],
+    errors: &mut Vec<Failure>,
+) {
+    if let Some(limit) = schema.get(min).and_then(Value::as_u64) {
+        if (len as u64) < limit {
+            fail(at, errors, format!("must have at least {} {}", limit, unit));
+        }
+    }
+    if let Some(limit) = schema.get(max).and_then(Value::as_u64) {
+        if len as u64 > limit {
+            fail(at, errors, format!("must have at most {} {}", limit, unit));
+        }
+    }
+}
+
+fn is_valid(v: &Value, schema: &Value) -> bool {
+    let mut errors = Vec::new();
+    validate(v, schema, &mut Vec::new(), &mut errors);
+    errors.is_empty()
+}
+
+/// JSON equality, under which numbers are equal if their values are, so
+/// `1` matches `1.0`.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Tagged(t), b) => same(&t.value, b),
+        (a, Value::Tagged(t)) => same(a, &t.value),
+        (Value::Number(x), Value::Number(y)) => match (x.as_i64(), y.as_i64()) {
+            (Some(x), Some(y)) => x == y,
+            _ if x.is_f64() || y.is_f64() => x.as_f64() == y.as_f64(),
+            _ => x.as_u64() == y.as_u64(),
+        },
+        (Value::Sequence(x), Value::Sequence(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| same(x, y))
+        }
+        (Value::Mapping(x), Value::Mapping(y)) => {
+            x.len() == y.len() && x.iter().all(|(k, v)| y.get(k).is_some_and(|w| same(v, w)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn has_type(v: &Value, t: &str) -> bool {
+    match (t, v) {
+        ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        (t, v) => type_of(v) == t,
+    }
+}
+
+fn type_of(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Sequence(_) => "array",
+        Value::Mapping(_) => "object",
+        Value::Tagged(t) => type_of(&t.value),
+    }
+}
+
+fn inline(v: &Value) -> String {
+    serde_yaml::to_string(v)
+        .map(|s| s.trim_end().to_owned())
+        .unwrap_or_default()
+}
+
+fn list(options: &[Value]) -> String {
+    options.iter().map(inline).collect::<Vec<_>>().join(", ")
+}
+
+fn fail(at: &[String], errors: &mut Vec<Failure>, message: String) {
+    errors.push((at.to_vec(), message));
+}
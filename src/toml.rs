@@ -0,0 +1,798 @@
+//! TOML reader and writer working directly on `serde_yaml::Value`.
+//!
+//! Covers the TOML 1.0 data model except that date-times are kept as
+//! strings, since `Value` has no date type.
+
+use serde_yaml::{Mapping, Number, Value};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+pub fn from_str(s: &str) -> Result<Value, String> {
+    let mut p = Parser {
+        src: s,
+        bytes: s.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    p.parse_document().map(Value::Mapping)
+}
+
+pub fn to_string(v: &Value) -> Result<String, String> {
+    let m = untag(v)
+        .as_mapping()
+        .ok_or_else(|| "TOML document root must be a table".to_owned())?;
+    let mut out = String::new();
+    write_table(&mut out, &mut Vec::new(), m)?;
+    Ok(out)
+}
+
+/// Deepest nesting of arrays and inline tables accepted, so a hostile file
+/// fails to parse instead of overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> String {
+        let consumed = &self.src[..self.pos];
+        let line = consumed.matches('\n').count() + 1;
+        let column = consumed.len() - consumed.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        format!("{} at line {} column {}", msg, line, column)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", s)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some(b'#') {
+            while let Some(b) = self.peek() {
+                if b == b'\n' {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and newlines.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+            if !self.eat("\n") && !self.eat("\r\n") {
+                return;
+            }
+        }
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        self.skip_comment();
+        if self.peek().is_none() || self.eat("\n") || self.eat("\r\n") {
+            Ok(())
+        } else {
+            Err(self.error("expected end of line"))
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Mapping, String> {
+        let mut root = Mapping::new();
+        let mut current: Vec<String> = Vec::new();
+        // Tables defined by a `[header]`, which may appear only once. A new
+        // `[[array]]` element starts over for the tables below it.
+        let mut defined: HashSet<Vec<String>> = HashSet::new();
+        loop {
+            self.skip_blank();
+            if self.peek().is_none() {
+                return Ok(root);
+            }
+            if self.eat("[[") {
+                let path = self.parse_key()?;
+                self.expect("]]")?;
+                let (last, parents) = path.split_last().unwrap();
+                let parent = self.table_at(&mut root, parents)?;
+                match parent
+                    .entry(Value::String(last.clone()))
+                    .or_insert_with(|| Value::Sequence(Vec::new()))
+                {
+                    Value::Sequence(seq) => seq.push(Value::Mapping(Mapping::new())),
+                    _ => return Err(self.error(&format!("`{}` is not an array", path.join(".")))),
+                }
+                defined.retain(|t| !t.starts_with(&path));
+                current = path;
+            } else if self.eat("[") {
+                let path = self.parse_key()?;
+                self.expect("]")?;
+                if !defined.insert(path.clone()) {
+                    let message = format!("table `{}` defined twice", path.join("."));
+                    return Err(self.error(&message));
+                }
+                self.table_at(&mut root, &path)?;
+                current = path;
+            } else {
+                let table = self.table_at(&mut root, &current)?;
+                self.parse_key_value(table)?;
+            }
+            self.expect_line_end()?;
+        }
+    }
+
+    /// Table at `path`, creating missing tables. Arrays of tables resolve to
+    /// their last element.
+    fn table_at<'m>(
+        &self,
+        root: &'m mut Mapping,
+        path: &[String],
+    ) -> Result<&'m mut Mapping, String> {
+        let mut table = root;
+        for segment in path {
+            let v = table
+                .entry(Value::String(segment.clone()))
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            table = match v {
+                Value::Mapping(m) => m,
+                Value::Sequence(seq) => match seq.last_mut() {
+                    Some(Value::Mapping(m)) => m,
+                    _ => return Err(self.error(&format!("`{}` is not a table", segment))),
+                },
+                _ => return Err(self.error(&format!("`{}` is not a table", segment))),
+            };
+        }
+        Ok(table)
+    }
+
+    fn parse_key_value(&mut self, table: &mut Mapping) -> Result<(), String> {
+        let path = self.parse_key()?;
+        self.skip_whitespace();
+        self.expect("=")?;
+        self.skip_whitespace();
+        let value = self.parse_value()?;
+        let (last, parents) = path.split_last().unwrap();
+        let parent = self.table_at(table, parents)?;
+        if parent.insert(Value::String(last.clone()), value).is_some() {
+            return Err(self.error(&format!("duplicate key `{}`", path.join("."))));
+        }
+        Ok(())
+    }
+
+    /// Parses a possibly dotted key into its segments.
+    fn parse_key(&mut self) -> Result<Vec<String>, String> {
+        let mut segments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let segment = match self.peek() {
+                Some(b'"') => self.parse_basic_string()?,
+                Some(b'\'') => self.parse_literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while let Some(b) = self.peek() {
+                        if !(b.is_ascii_alphanumeric() || b == b'_' || b == b'-') {
+                            break;
+                        }
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err(self.error("expected key"));
+                    }
+                    self.src[start..self.pos].to_owned()
+                }
+            };
+            segments.push(segment);
+            self.skip_whitespace();
+            if !self.eat(".") {
+                return Ok(segments);
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        let rest = self.rest();
+        if rest.starts_with("\"\"\"") {
+            self.parse_multiline_basic_string().map(Value::String)
+        } else if rest.starts_with("'''") {
+            self.parse_multiline_literal_string().map(Value::String)
+        } else if rest.starts_with('"') {
+            self.parse_basic_string().map(Value::String)
+        } else if rest.starts_with('\'') {
+            self.parse_literal_string().map(Value::String)
+        } else if self.eat("true") {
+            Ok(Value::Bool(true))
+        } else if self.eat("false") {
+            Ok(Value::Bool(false))
+        } else if rest.starts_with('[') {
+            self.nested(Self::parse_array)
+        } else if rest.starts_with('{') {
+            self.nested(Self::parse_inline_table)
+        } else {
+            self.parse_number_or_datetime()
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Value, String>
+    where
+        F: FnOnce(&mut Self) -> Result<Value, String>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let v = parse(self);
+        self.depth -= 1;
+        v
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        self.expect("[")?;
+        let mut seq = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.eat("]") {
+                return Ok(Value::Sequence(seq));
+            }
+            seq.push(self.parse_value()?);
+            self.skip_blank();
+            if !self.eat(",") {
+                self.skip_blank();
+                self.expect("]")?;
+                return Ok(Value::Sequence(seq));
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Value, String> {
+        self.expect("{")?;
+        let mut table = Mapping::new();
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Value::Mapping(table));
+        }
+        loop {
+            self.parse_key_value(&mut table)?;
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Value::Mapping(table));
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn parse_number_or_datetime(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            let date_time_space = b == b' '
+                && self.pos - start == 10
+                && self.bytes.get(self.pos + 1).is_some_and(u8::is_ascii_digit);
+            if !(b.is_ascii_alphanumeric() || b"_+-.:".contains(&b) || date_time_space) {
+                break;
+            }
+            self.pos += 1;
+        }
+        let token = &self.src[start..self.pos];
+        let b = token.as_bytes();
+        let is_date = b.len() >= 10 && b[4] == b'-' && b[7] == b'-';
+        let is_time = b.len() >= 8 && b[2] == b':' && b[5] == b':';
+        if is_date || is_time {
+            return Ok(Value::String(token.to_owned()));
+        }
+
+        let invalid = |p: &mut Self| {
+            p.pos = start;
+            Err(p.error("invalid value"))
+        };
+        for (prefix, radix) in &[("0x", 16), ("0o", 8), ("0b", 2)] {
+            if let Some(d) = token.strip_prefix(prefix) {
+                if !is_digit_groups(d, |c| c.is_digit(*radix)) {
+                    return invalid(self);
+                }
+                return match i64::from_str_radix(&d.replace('_', ""), *radix) {
+                    Ok(i) => Ok(Value::Number(i.into())),
+                    Err(_) => invalid(self),
+                };
+            }
+        }
+        let digits = token.replace('_', "");
+        let unsigned = digits.trim_start_matches(['+', '-']);
+        match unsigned {
+            "inf" | "nan" => {
+                let f = if unsigned == "nan" {
+                    f64::NAN
+                } else {
+                    f64::INFINITY
+                };
+                let f = if digits.starts_with('-') { -f } else { f };
+                return Ok(Value::Number(f.into()));
+            }
+            _ if !is_decimal(token.trim_start_matches(['+', '-'])) => return invalid(self),
+            _ => {}
+        }
+        if digits.contains(['.', 'e', 'E']) {
+            match digits.parse::<f64>() {
+                Ok(f) => Ok(Value::Number(f.into())),
+                Err(_) => invalid(self),
+            }
+        } else {
+            match digits.parse::<i64>() {
+                Ok(i) => Ok(Value::Number(i.into())),
+                Err(_) => invalid(self),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, String> {
+        self.expect("'")?;
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            match b {
+                b'\'' => {
+                    let s = self.src[start..self.pos].to_owned();
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                b'\n' => break,
+                _ => self.pos += 1,
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn parse_multiline_literal_string(&mut self) -> Result<String, String> {
+        self.expect("'''")?;
+        if !self.eat("\n") {
+            self.eat("\r\n");
+        }
+        let end = self
+            .rest()
+            .find("'''")
+            .ok_or_else(|| self.error("unterminated string"))?;
+        // Up to two quotes directly before the closing delimiter belong to the content.
+        let extra = self.rest()[end + 3..]
+            .bytes()
+            .take(2)
+            .take_while(|b| *b == b'\'')
+            .count();
+        let s = self.rest()[..end + extra].to_owned();
+        self.pos += end + extra + 3;
+        Ok(s)
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => out.push(self.parse_escape()?),
+                Some(b'\n') | None => return Err(self.error("unterminated string")),
+                Some(_) => self.push_char(&mut out),
+            }
+        }
+    }
+
+    fn parse_multiline_basic_string(&mut self) -> Result<String, String> {
+        self.expect("\"\"\"")?;
+        if !self.eat("\n") {
+            self.eat("\r\n");
+        }
+        let mut out = String::new();
+        loop {
+            if self.eat("\"\"\"") {
+                // Up to two quotes directly before the closing delimiter belong to the content.
+                for _ in 0..2 {
+                    if self.eat("\"") {
+                        out.push('"');
+                    }
+                }
+                return Ok(out);
+            }
+            match self.peek() {
+                Some(b'\\') => {
+                    // A backslash at the end of a line trims the following whitespace.
+                    let trimmed = self.rest()[1..].trim_start_matches([' ', '\t']);
+                    if trimmed.starts_with('\n') || trimmed.starts_with("\r\n") {
+                        let after = trimmed.trim_start_matches(char::is_whitespace);
+                        self.pos = self.src.len() - after.len();
+                    } else {
+                        out.push(self.parse_escape()?);
+                    }
+                }
+                None => return Err(self.error("unterminated string")),
+                Some(_) => self.push_char(&mut out),
+            }
+        }
+    }
+
+    fn push_char(&mut self, out: &mut String) {
+        let c = self.rest().chars().next().unwrap();
+        out.push(c);
+        self.pos += c.len_utf8();
+    }
+
+    fn parse_escape(&mut self) -> Result<char, String> {
+        self.expect("\\")?;
+        let c = match self.peek() {
+            Some(b'b') => '\u{8}',
+            Some(b't') => '\t',
+            Some(b'n') => '\n',
+            Some(b'f') => '\u{c}',
+            Some(b'r') => '\r',
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'u') => return self.parse_unicode_escape(4),
+            Some(b'U') => return self.parse_unicode_escape(8),
+            _ => return Err(self.error("invalid escape")),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn parse_unicode_escape(&mut self, len: usize) -> Result<char, String> {
+        self.pos += 1;
+        let c = self
+            .src
+            .get(self.pos..self.pos + len)
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += len;
+        Ok(c)
+    }
+}
+
+fn untag(v: &Value) -> &Value {
+    match v {
+        Value::Tagged(t) => untag(&t.value),
+        v => v,
+    }
+}
+
+fn is_table(v: &Value) -> bool {
+    untag(v).is_mapping()
+}
+
+fn is_table_array(v: &Value) -> bool {
+    match untag(v) {
+        Value::Sequence(seq) => !seq.is_empty() && seq.iter().all(is_table),
+        _ => false,
+    }
+}
+
+/// Whether `s` is an unsigned decimal integer or float: no leading zeros
+/// and digits on both sides of `.`, with `_` only between digits.
+fn is_decimal(s: &str) -> bool {
+    let is_digits = |s: &str| is_digit_groups(s, |c| c.is_ascii_digit());
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (int, frac) = match mantissa.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (mantissa, None),
+    };
+    is_digits(int)
+        && (int == "0" || !int.starts_with('0'))
+        && frac.is_none_or(is_digits)
+        && exponent.is_none_or(|e| is_digits(e.strip_prefix(['+', '-']).unwrap_or(e)))
+}
+
+/// Whether `s` is one or more digits, with single `_` allowed between them.
+fn is_digit_groups(s: &str, is_digit: impl Fn(char) -> bool) -> bool {
+    !s.is_empty()
+        && s.split('_')
+            .all(|group| !group.is_empty() && group.chars().all(&is_digit))
+}
+
+fn write_table(out: &mut String, path: &mut Vec<String>, m: &Mapping) -> Result<(), String> {
+    // Plain key/value pairs have to come before any sub-table header.
+    for (k, v) in m {
+        if is_table(v) || is_table_array(v) || untag(v).is_null() {
+            continue;
+        }
+        write_key(out, &key_to_string(k)?);
+        out.push_str(" = ");
+        write_inline(out, v)?;
+        out.push('\n');
+    }
+    for (k, v) in m {
+        path.push(key_to_string(k)?);
+        match untag(v) {
+            Value::Mapping(sub) => {
+                write_header(out, path, "[", "]");
+                write_table(out, path, sub)?;
+            }
+            Value::Sequence(seq) if is_table_array(v) => {
+                for item in seq {
+                    write_header(out, path, "[[", "]]");
+                    write_table(out, path, untag(item).as_mapping().unwrap())?;
+                }
+            }
+            _ => {}
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+fn write_header(out: &mut String, path: &[String], open: &str, close: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(open);
+    for (i, segment) in path.iter().enumerate() {
+        if i > 0 {
+            out.push('.');
+        }
+        write_key(out, segment);
+    }
+    out.push_str(close);
+    out.push('\n');
+}
+
+fn write_key(out: &mut String, key: &str) {
+    let bare = !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if bare {
+        out.push_str(key);
+    } else {
+        write_string(out, key);
+    }
+}
+
+/// TOML keys are always strings; scalar YAML keys are stringified.
+fn key_to_string(k: &Value) -> Result<String, String> {
+    match untag(k) {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        k => Err(format!("TOML key must be a scalar, found {:?}", k)),
+    }
+}
+
+fn write_inline(out: &mut String, v: &Value) -> Result<(), String> {
+    match untag(v) {
+        Value::Null => return Err("null can't be represented in TOML".to_owned()),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(out, n)?,
+        Value::String(s) => write_string(out, s),
+        Value::Sequence(seq) => {
+            out.push('[');
+            for (i, item) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_inline(out, item)?;
+            }
+            out.push(']');
+        }
+        Value::Mapping(m) => {
+            out.push('{');
+            let mut first = true;
+            for (k, item) in m {
+                if untag(item).is_null() {
+                    continue;
+                }
+                out.push_str(if first { " " } else { ", " });
+                first = false;
+                write_key(out, &key_to_string(k)?);
+                out.push_str(" = ");
+                write_inline(out, item)?;
+            }
+            out.push_str(if first { "}" } else { " }" });
+        }
+        Value::Tagged(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+fn write_number(out: &mut String, n: &Number) -> Result<(), String> {
+    if let Some(i) = n.as_i64() {
+        write!(out, "{}", i).unwrap();
+    } else if n.is_u64() {
+        return Err(format!("{} is out of range for a TOML integer", n));
+    } else {
+        let f = n.as_f64().unwrap();
+        if f.is_nan() {
+            out.push_str("nan");
+        } else if f.is_infinite() {
+            out.push_str(if f > 0.0 { "inf" } else { "-inf" });
+        } else {
+            // Debug keeps a fractional part, so the value reads back as a float.
+            write!(out, "{:?}", f).unwrap();
+        }
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => write!(out, "\\u{:04X}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_str, to_string};
+    use serde_yaml::Value;
+
+    #[test]
+    fn parse_document() {
+        let input = r#"
+# comment
+title = "TOML \"example\""
+literal = 'C:\path'
+multi = """
+one \
+  two"""
+int = 1_000
+hex = 0xff
+float = -2.5e3
+date = 1979-05-27T07:32:00Z
+site."google.com" = true
+
+[database]
+ports = [ 8000, 8001,
+  8002, ] # trailing comma
+inline = { x = 1, y.z = [] }
+
+[[servers]]
+name = "alpha"
+
+[[servers]]
+name = "beta"
+"#;
+        let v = from_str(input).unwrap();
+        assert_eq!(v["title"], Value::from("TOML \"example\""));
+        assert_eq!(v["literal"], Value::from("C:\\path"));
+        assert_eq!(v["multi"], Value::from("one two"));
+        assert_eq!(v["int"], Value::from(1000));
+        assert_eq!(v["hex"], Value::from(255));
+        assert_eq!(v["float"], Value::from(-2500.0));
+        assert_eq!(v["date"], Value::from("1979-05-27T07:32:00Z"));
+        assert_eq!(v["site"]["google.com"], Value::from(true));
+        assert_eq!(v["database"]["ports"][2], Value::from(8002));
+        assert_eq!(v["database"]["inline"]["y"]["z"], Value::Sequence(vec![]));
+        assert_eq!(v["servers"][1]["name"], Value::from("beta"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(from_str("a = ").is_err());
+        assert!(from_str("a = 1\na = 2").is_err());
+        assert!(from_str("a = 1 b = 2").is_err());
+        assert!(from_str("a = \"open").is_err());
+        assert!(from_str("a = 1\n[a]").is_err());
+        for value in &[
+            "01", "+01", "00", "01.5", "1.", "1.e3", ".5", "1e", "1e+", "1_", "1__0", "1_.5",
+            "0x-1", "0x+1", "0x", "0x_1", "0b2", "0o8",
+        ] {
+            assert!(
+                from_str(&format!("a = {}", value)).is_err(),
+                "accepted {}",
+                value
+            );
+        }
+        assert!(from_str("[a]\nx = 1\n[a]\ny = 2").is_err());
+        assert!(from_str("[a.b]\n[a]\n[a.b]").is_err());
+    }
+
+    #[test]
+    fn valid_numbers_and_tables() {
+        for (text, v) in &[
+            ("0", Value::from(0)),
+            ("+0", Value::from(0)),
+            ("-17", Value::from(-17)),
+            ("1_000", Value::from(1000)),
+            ("0.5", Value::from(0.5)),
+            ("-1_0.2_5e1_0", Value::from(-10.25e10)),
+            ("5E+2", Value::from(500.0)),
+            ("0xdead_beef", Value::from(0xdead_beef_i64)),
+            ("0o17", Value::from(15)),
+            ("0b101", Value::from(5)),
+        ] {
+            let v2 = from_str(&format!("a = {}", text)).unwrap();
+            assert_eq!(&v2["a"], v, "{}", text);
+        }
+        let v = from_str("[a.b]\nx = 1\n[a]\ny = 2\n[[t]]\n[t.s]\n[[t]]\n[t.s]\n").unwrap();
+        assert_eq!(v["a"]["b"]["x"], Value::from(1));
+        assert_eq!(v["t"].as_sequence().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| "a = ".to_owned() + &"[".repeat(depth) + &"]".repeat(depth);
+        assert!(from_str(&nested(128)).is_ok());
+        assert!(from_str(&nested(129))
+            .unwrap_err()
+            .starts_with("nesting too deep"));
+        assert!(from_str(&("a = ".to_owned() + &"[".repeat(400_000))).is_err());
+        assert!(from_str(&("a = ".to_owned() + &"{ b = ".repeat(200))).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "a = 1
+b = [1.5, \"x\", { c = true }]
+
+[d]
+e = \"f\"
+
+[d.g]
+h = -1
+
+[[i]]
+j = 1
+
+[[i]]
+j = 2
+";
+        let v = from_str(input).unwrap();
+        assert_eq!(to_string(&v).unwrap(), input);
+
+        let v = from_str("inline = { a = 1, b = { c = 2 } }").unwrap();
+        assert_eq!(
+            to_string(&v).unwrap(),
+            "[inline]\na = 1\n\n[inline.b]\nc = 2\n"
+        );
+        assert_eq!(from_str(&to_string(&v).unwrap()).unwrap(), v);
+    }
+
+    #[test]
+    fn nulls() {
+        let v: Value = serde_yaml::from_str("a: ~\nb: {c: ~, d: 1}\n").unwrap();
+        assert_eq!(to_string(&v).unwrap(), "[b]\nd = 1\n");
+        let v: Value = serde_yaml::from_str("a: [1, ~]\n").unwrap();
+        assert!(to_string(&v).is_err());
+    }
+
+    #[test]
+    fn root_not_table() {
+        assert!(to_string(&Value::from(1)).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{Error, Format};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// A config operation or a problem the config worked around, reported to
+/// the hook set with `set_trace_hook`. Events carry paths and key names,
+/// never values, so secrets don't end up in logs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceEvent<'a> {
+    /// A file was read by `read_from_file`.
+    Read { path: &'a Path },
+    /// `reload` is about to re-read a file.
+    Reload { path: &'a Path },
+    /// A file was written by `write_to_file`.
+    Write { path: &'a Path },
+    /// A value was stored with `add` or `set`.
+    Add { key: &'a str },
+    /// A value was removed with `remove`.
+    Remove { key: &'a str },
+    /// `with_autosave` couldn't save the config when it was dropped.
+    SaveOnDropFailed { error: &'a Error },
+    /// A case-insensitive lookup of `key` matched several keys and used
+    /// `used`, see `with_case_insensitive`.
+    AmbiguousKey { key: &'a str, used: &'a str },
+    /// The file is used as `format` despite its extension, see
+    /// `with_strict_extension`.
+    ExtensionMismatch { path: &'a Path, format: Format },
+    /// The header comment was left out because `format` has no comments.
+    HeaderSkipped { format: Format },
+}
+
+static HOOK: RwLock<Option<fn(&TraceEvent)>> = RwLock::new(None);
+
+/// Sets the function called with every `TraceEvent` of every config in the
+/// process, typically forwarding it to the application's logger. `None`
+/// removes it again. Without the `trace` feature no events are emitted at
+/// all.
+pub fn set_trace_hook(hook: Option<fn(&TraceEvent)>) {
+    *HOOK.write().unwrap_or_else(|e| e.into_inner()) = hook;
+}
+
+pub(crate) fn emit(event: TraceEvent) {
+    let hook = *HOOK.read().unwrap_or_else(|e| e.into_inner());
+    if let Some(hook) = hook {
+        hook(&event);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{Config, Error};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keeps a config file watcher running; stops it when dropped.
+pub struct WatchHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the watcher up immediately.
+        self.stop.take();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+impl Config {
+    /// Watches the configured file in a background thread and calls
+    /// `on_change` with the reloaded config every time the file changes and
+    /// parses successfully. Failed reloads are skipped and the previous
+    /// values are kept.
+    ///
+    /// The file's modification time and size are polled every 500ms, and a
+    /// change is only picked up once they've stayed the same for another
+    /// poll, so a burst of writes results in a single reload.
+    pub fn watch<F>(self, on_change: F) -> Result<WatchHandle, Error>
+    where
+        F: FnMut(&Config) + Send + 'static,
+    {
+        self.watch_every(DEFAULT_INTERVAL, on_change)
+    }
+
+    /// Like `watch`, polling every `interval` instead of the default 500ms.
+    pub fn watch_every<F>(
+        mut self,
+        interval: Duration,
+        mut on_change: F,
+    ) -> Result<WatchHandle, Error>
+    where
+        F: FnMut(&Config) + Send + 'static,
+    {
+        let path = self.file()?.to_owned();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let wait = || {
+                matches!(
+                    stopped.recv_timeout(interval),
+                    Err(RecvTimeoutError::Timeout)
+                )
+            };
+            let mut last = stamp(&path);
+            while wait() {
+                let mut current = stamp(&path);
+                if current == last {
+                    continue;
+                }
+                // Debounce: wait for the file to settle before reloading.
+                loop {
+                    if !wait() {
+                        return;
+                    }
+                    let next = stamp(&path);
+                    if next == current {
+                        break;
+                    }
+                    current = next;
+                }
+                last = current;
+                if self.reload().is_ok() {
+                    on_change(&self);
+                }
+            }
+        });
+        Ok(WatchHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}